@@ -0,0 +1,166 @@
+use num_derive::FromPrimitive;
+use solana_program::{
+    decode_error::DecodeError,
+    msg,
+    program_error::{PrintProgramError, ProgramError},
+};
+use thiserror::Error;
+
+// Codes are part of the client-facing API: append new variants, never renumber
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Error, FromPrimitive)]
+pub enum VaultError {
+    #[error("Signer does not match the vault authority")]
+    InvalidAuthority = 0,
+    #[error("Vault does not match the address derived from its seeds")]
+    InvalidVaultAddress = 1,
+    #[error("Required signature is missing")]
+    MissingSigner = 2,
+    #[error("Account is not initialized")]
+    UninitializedVault = 3,
+    #[error("Account is not owned by this program")]
+    InvalidVaultOwner = 4,
+    #[error("Vault state version is not supported")]
+    UnsupportedVersion = 5,
+    #[error("Arithmetic overflow")]
+    ArithmeticOverflow = 6,
+    #[error("Withdrawal amount is too small")]
+    WithdrawalTooSmall = 7,
+    #[error("Invalid instruction data")]
+    InvalidInstruction = 8,
+    #[error("Vault has no lamports available above its rent-exempt reserve")]
+    InsufficientVaultFunds = 9,
+    #[error("Recipient cannot be the vault itself")]
+    InvalidRecipient = 10,
+    #[error("Withdrawal policy must be between 1 and 10000 basis points")]
+    InvalidPolicy = 11,
+    #[error("Vault is already initialized")]
+    AlreadyInitialized = 12,
+    #[error("Account is of the wrong type for this instruction")]
+    InvalidAccountType = 13,
+    #[error("Account passed as the system program is not the system program")]
+    InvalidSystemProgram = 14,
+    #[error("Payer account must be writable")]
+    PayerNotWritable = 15,
+    #[error("Vault account must be writable")]
+    VaultNotWritable = 16,
+    #[error("Payer must sign")]
+    PayerNotSigner = 17,
+    #[error("Token program is not SPL Token or Token-2022, or does not own the mint")]
+    InvalidTokenProgram = 18,
+    #[error("Token account is not the vault's associated token account for the mint")]
+    InvalidVaultTokenAccount = 19,
+    #[error("Account passed as the associated token program is not the associated token program")]
+    InvalidAssociatedTokenProgram = 20,
+    #[error("Vault token record does not match the vault and mint")]
+    InvalidVaultTokenState = 21,
+    #[error("Vault still holds balances that must be withdrawn first")]
+    OutstandingObligations = 22,
+    #[error("Deposit receipt does not match the vault and depositor")]
+    InvalidReceipt = 23,
+    #[error("Depositor has nothing left to redeem")]
+    NothingToRedeem = 24,
+    #[error("Vault issues share tokens; use the share deposit and redeem instructions")]
+    SharesEnabled = 25,
+    #[error("Vault does not issue share tokens")]
+    SharesNotEnabled = 26,
+    #[error("Share mint does not belong to the vault")]
+    InvalidShareMint = 27,
+    #[error("Deposit is too small to mint any shares")]
+    DepositTooSmall = 28,
+    #[error("Vault is locked until its unlock time")]
+    VaultLocked = 29,
+    #[error("Withdrawal cooldown has not elapsed")]
+    WithdrawalCooldown = 30,
+    #[error("Withdrawal lock can only be extended")]
+    LockCannotBeShortened = 31,
+    #[error("Withdrawal lock values must not be negative")]
+    InvalidLock = 32,
+    #[error("Withdrawal exceeds the vault's outflow limit for the current window")]
+    RateLimitExceeded = 33,
+    #[error("Rate limit needs a positive window and at least one cap of at most 10000 bps")]
+    InvalidRateLimit = 34,
+    #[error("Rate limit can only be tightened")]
+    RateLimitCannotBeLoosened = 35,
+    #[error("Vault has a withdrawal delay; request the withdrawal and execute it later")]
+    WithdrawalDelayRequired = 36,
+    #[error("Pending withdrawal has not reached its execution time")]
+    WithdrawalNotMature = 37,
+    #[error("Pending withdrawal does not belong to the vault")]
+    InvalidPendingWithdrawal = 38,
+    #[error("Deposits are paused by the vault guardian")]
+    DepositsPaused = 39,
+    #[error("Withdrawals are paused by the vault guardian")]
+    WithdrawalsPaused = 40,
+    #[error("Signer is not the vault guardian")]
+    InvalidGuardian = 41,
+    #[error("Vault withdrawals are governed by a multisig")]
+    MultisigRequired = 42,
+    #[error("Invalid multisig config")]
+    InvalidMultisig = 43,
+    #[error("Signer is not a member of the vault multisig")]
+    InvalidMultisigSigner = 44,
+    #[error("Proposal does not belong to the multisig")]
+    InvalidProposal = 45,
+    #[error("Signer already approved this proposal")]
+    AlreadyApproved = 46,
+    #[error("Proposal has not reached the approval threshold")]
+    ThresholdNotReached = 47,
+    #[error("Account must be writable")]
+    AccountNotWritable = 48,
+    #[error("Recipient is not on the vault allowlist")]
+    RecipientNotAllowed = 49,
+    #[error("Recipient allowlist is full")]
+    AllowlistFull = 50,
+    #[error("Allowlist does not belong to the vault")]
+    InvalidAllowlist = 51,
+    #[error("Recipient is already on the allowlist")]
+    RecipientAlreadyAllowed = 52,
+    #[error("No authority transfer is pending")]
+    NoPendingAuthority = 53,
+    #[error("Vault funds are governed by a vesting schedule")]
+    VestingEnabled = 54,
+    #[error("Invalid vesting schedule")]
+    InvalidVestingSchedule = 55,
+    #[error("Vesting schedule does not belong to the vault")]
+    InvalidVesting = 56,
+    #[error("Nothing has vested since the last claim")]
+    NothingToClaim = 57,
+    #[error("Vesting schedule cannot be revoked")]
+    VestingNotRevocable = 58,
+    #[error("Stream does not belong to the vault")]
+    InvalidStream = 59,
+    #[error("Invalid stream schedule")]
+    InvalidStreamSchedule = 60,
+    #[error("Nothing has accrued on the stream since the last settlement")]
+    NothingToSettle = 61,
+    #[error("Escrow account does not match its parties")]
+    InvalidEscrow = 62,
+    #[error("Escrow is not in a state that allows this action")]
+    InvalidEscrowState = 63,
+    #[error("Escrow has not expired yet")]
+    EscrowNotExpired = 64,
+    #[error("Escrow has expired")]
+    EscrowExpired = 65,
+    #[error("Escrow has no arbiter")]
+    NoArbiter = 66,
+    #[error("Dispute has not timed out yet")]
+    DisputeNotTimedOut = 67,
+}
+
+impl From<VaultError> for ProgramError {
+    fn from(e: VaultError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl<T> DecodeError<T> for VaultError {
+    fn type_of() -> &'static str {
+        "VaultError"
+    }
+}
+
+impl PrintProgramError for VaultError {
+    fn print<E>(&self) {
+        msg!("Error: {}", self);
+    }
+}
@@ -1,4 +1,6 @@
+pub mod error;
 pub mod processor;
+pub mod state;
 
 use {crate::processor::process_instruction, solana_program::entrypoint};
 
@@ -1,46 +1,299 @@
+use crate::{
+    error::VaultError,
+    state::{
+        apply_bps, find_allowlist_address, find_escrow_address, find_multisig_address,
+        find_pending_withdrawal_address, find_proposal_address, find_receipt_address,
+        find_share_mint_address, find_stream_address, find_vault_address, find_vault_token_address,
+        find_vesting_address, mul_div, validate_withdrawal_bps, DepositReceipt, EscrowState,
+        EscrowStatus, MultisigConfig, PayoutStream, PendingWithdrawal, ProgramAccount,
+        RecipientAllowlist, VaultState, VaultTokenState, VestingSchedule, WithdrawalProposal,
+        ALLOWLIST_SEED, ESCROW_SEED, MAX_BPS, MULTISIG_SEED, PROPOSAL_SEED, RECEIPT_SEED,
+        SHARE_DECIMALS, SHARE_MINT_SEED, STREAM_SEED, VAULT_SEED, VAULT_TOKEN_SEED, VESTING_SEED,
+        WITHDRAWAL_SEED,
+    },
+};
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    clock::Clock,
     entrypoint::ProgramResult,
-    program::invoke,
-    program_error::ProgramError,
+    program::{invoke, invoke_signed},
+    program_error::{PrintProgramError, ProgramError},
+    program_pack::Pack,
     pubkey::Pubkey,
     rent::Rent,
-    system_instruction,
+    system_instruction, system_program,
     sysvar::Sysvar,
 };
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use spl_token_2022::{
+    extension::StateWithExtensions,
+    onchain::invoke_transfer_checked,
+    state::{Account as TokenAccount, Mint},
+};
 
-// Constants for better readability and maintainability
-const DEPOSIT_ACCOUNT_SIZE: usize = 8;
-const WITHDRAWAL_PERCENTAGE: u64 = 10;
+// Re-derive the vault address from the owner and bump stored in its state
+fn check_vault_address(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    vault: &VaultState,
+) -> ProgramResult {
+    let expected = Pubkey::create_program_address(
+        &[VAULT_SEED, vault.owner.as_ref(), &[vault.bump]],
+        program_id,
+    )
+    .map_err(|_| VaultError::InvalidVaultAddress)?;
+    if expected != *deposit_account.key {
+        return Err(VaultError::InvalidVaultAddress.into());
+    }
+    Ok(())
+}
 
-pub fn deposit(_program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
-    let accounts_iter = &mut accounts.iter();
-    let payer = next_account_info(accounts_iter)?;
-    let deposit_account = next_account_info(accounts_iter)?;
-    let system_program = next_account_info(accounts_iter)?;
+// Check the accounts that pay for an account or a transfer before invoking the
+// system program, so integrators get a specific error instead of an opaque
+// runtime failure
+fn check_payer(payer: &AccountInfo, system_program: &AccountInfo) -> ProgramResult {
+    if *system_program.key != system_program::ID {
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+    if !payer.is_signer {
+        return Err(VaultError::PayerNotSigner.into());
+    }
+    if !payer.is_writable {
+        return Err(VaultError::PayerNotWritable.into());
+    }
+    Ok(())
+}
+
+// Same, for instructions that fund or grow the vault itself
+fn check_funding_accounts(
+    payer: &AccountInfo,
+    deposit_account: &AccountInfo,
+    system_program: &AccountInfo,
+) -> ProgramResult {
+    check_payer(payer, system_program)?;
+    if !deposit_account.is_writable {
+        return Err(VaultError::VaultNotWritable.into());
+    }
+    Ok(())
+}
 
-    // Check if the deposit account is already initialized
-    if deposit_account.data_is_empty() {
-        // If not, initialize it
-        let rent = Rent::get()?;
-        let rent_lamports = rent.minimum_balance(DEPOSIT_ACCOUNT_SIZE);
+// Lamports held above the vault's rent-exempt minimum
+fn available_lamports(deposit_account: &AccountInfo) -> Result<u64, ProgramError> {
+    let rent_lamports = Rent::get()?.minimum_balance(deposit_account.data_len());
+    Ok(deposit_account.lamports().saturating_sub(rent_lamports))
+}
 
-        invoke(
+// Move lamports out of a program-owned account, checking both sides for overflow
+fn transfer_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> ProgramResult {
+    let from_lamports = from
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(VaultError::InsufficientVaultFunds)?;
+    let to_lamports = to
+        .lamports()
+        .checked_add(amount)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    **from.try_borrow_mut_lamports()? = from_lamports;
+    **to.try_borrow_mut_lamports()? = to_lamports;
+    Ok(())
+}
+
+// Create an account owned by `owner` at a PDA, tolerating lamports that were sent
+// to the address ahead of time
+fn create_pda_account<'a>(
+    payer: &AccountInfo<'a>,
+    new_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    owner: &Pubkey,
+    space: usize,
+    signer_seeds: &[&[u8]],
+) -> ProgramResult {
+    let rent = Rent::get()?;
+    let rent_lamports = rent.minimum_balance(space);
+    let current_lamports = new_account.lamports();
+
+    if current_lamports == 0 {
+        invoke_signed(
             &system_instruction::create_account(
                 payer.key,
-                deposit_account.key,
+                new_account.key,
                 rent_lamports,
-                DEPOSIT_ACCOUNT_SIZE as u64,
-                _program_id,
+                space as u64,
+                owner,
             ),
-            &[
-                payer.clone(),
-                deposit_account.clone(),
-                system_program.clone(),
-            ],
+            &[payer.clone(), new_account.clone(), system_program.clone()],
+            &[signer_seeds],
+        )?;
+    } else {
+        // Someone already sent lamports to the address, so create_account would fail.
+        // Top it up to rent exemption and allocate/assign it instead.
+        let shortfall = rent_lamports.saturating_sub(current_lamports);
+        if shortfall > 0 {
+            invoke(
+                &system_instruction::transfer(payer.key, new_account.key, shortfall),
+                &[payer.clone(), new_account.clone(), system_program.clone()],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(new_account.key, space as u64),
+            &[new_account.clone(), system_program.clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(new_account.key, owner),
+            &[new_account.clone(), system_program.clone()],
+            &[signer_seeds],
         )?;
     }
+    Ok(())
+}
+
+pub fn initialize_vault(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    authority: Pubkey,
+    withdrawal_bps: u16,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let payer = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    check_funding_accounts(payer, deposit_account, system_program)?;
+    validate_withdrawal_bps(withdrawal_bps)?;
+
+    // The vault lives at the PDA derived from the payer
+    let (expected, bump) = find_vault_address(payer.key, program_id);
+    if expected != *deposit_account.key {
+        return Err(VaultError::InvalidVaultAddress.into());
+    }
+    // Refuse to re-initialize a vault, or to overwrite any other data at the address
+    if VaultState::is_initialized(deposit_account)? {
+        return Err(VaultError::AlreadyInitialized.into());
+    }
+    if !deposit_account.data_is_empty() {
+        return Err(VaultError::InvalidAccountType.into());
+    }
+
+    create_pda_account(
+        payer,
+        deposit_account,
+        system_program,
+        program_id,
+        VaultState::LEN,
+        &[VAULT_SEED, payer.key.as_ref(), &[bump]],
+    )?;
+
+    let clock = Clock::get()?;
+    VaultState::new(
+        *payer.key,
+        authority,
+        bump,
+        withdrawal_bps,
+        clock.unix_timestamp,
+    )
+    .store(deposit_account)
+}
+
+// Load a depositor's receipt and make sure it belongs to this vault and depositor
+fn load_receipt(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    depositor: &AccountInfo,
+    receipt_account: &AccountInfo,
+) -> Result<DepositReceipt, ProgramError> {
+    let receipt = DepositReceipt::load_account(receipt_account, program_id)?;
+    if receipt.vault != *deposit_account.key || receipt.depositor != *depositor.key {
+        return Err(VaultError::InvalidReceipt.into());
+    }
+    let expected = Pubkey::create_program_address(
+        &[
+            RECEIPT_SEED,
+            deposit_account.key.as_ref(),
+            depositor.key.as_ref(),
+            &[receipt.bump],
+        ],
+        program_id,
+    )
+    .map_err(|_| VaultError::InvalidReceipt)?;
+    if expected != *receipt_account.key {
+        return Err(VaultError::InvalidReceipt.into());
+    }
+    Ok(receipt)
+}
+
+fn load_or_create_receipt<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    deposit_account: &AccountInfo<'a>,
+    receipt_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> Result<DepositReceipt, ProgramError> {
+    if DepositReceipt::is_initialized(receipt_account)? {
+        return load_receipt(program_id, deposit_account, payer, receipt_account);
+    }
+    let (expected, bump) = find_receipt_address(deposit_account.key, payer.key, program_id);
+    if expected != *receipt_account.key {
+        return Err(VaultError::InvalidReceipt.into());
+    }
+    if !receipt_account.data_is_empty() {
+        return Err(VaultError::InvalidAccountType.into());
+    }
+    create_pda_account(
+        payer,
+        receipt_account,
+        system_program,
+        program_id,
+        DepositReceipt::LEN,
+        &[
+            RECEIPT_SEED,
+            deposit_account.key.as_ref(),
+            payer.key.as_ref(),
+            &[bump],
+        ],
+    )?;
+    Ok(DepositReceipt::new(*deposit_account.key, *payer.key, bump))
+}
+
+pub fn deposit(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let payer = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+    // Receipts are opt-in: without one the deposit funds the authority, as
+    // deposits always did, and only deposits with a receipt are owed back
+    let receipt_account = next_account_info(accounts_iter).ok();
+
+    check_funding_accounts(payer, deposit_account, system_program)?;
+
+    // Deposits only go into vaults created by InitializeVault
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    check_deposits_open(&vault)?;
+    check_not_vesting(&vault)?;
+
+    // Share vaults account for depositors through their share tokens instead
+    if vault.has_shares() {
+        return Err(VaultError::SharesEnabled.into());
+    }
+
+    let receipt = match receipt_account {
+        Some(receipt_account) => Some((
+            load_or_create_receipt(
+                program_id,
+                payer,
+                deposit_account,
+                receipt_account,
+                system_program,
+            )?,
+            receipt_account,
+        )),
+        None => None,
+    };
 
     // Transfer the deposit amount
     invoke(
@@ -52,61 +305,2532 @@ pub fn deposit(_program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> P
         ],
     )?;
 
-    // Update the total deposited amount
-    let mut deposit_data = deposit_account.try_borrow_mut_data()?;
-    let mut total_deposited = u64::from_le_bytes(deposit_data[..8].try_into().unwrap());
-    total_deposited += amount;
-    deposit_data[..8].copy_from_slice(&total_deposited.to_le_bytes());
+    // Update the total deposited amount, on the vault and on the receipt
+    vault.total_deposited = vault
+        .total_deposited
+        .checked_add(amount)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+
+    if let Some((mut receipt, receipt_account)) = receipt {
+        vault.outstanding_principal = vault
+            .outstanding_principal
+            .checked_add(amount)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        receipt.total_deposited = receipt
+            .total_deposited
+            .checked_add(amount)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        receipt.principal = receipt
+            .principal
+            .checked_add(amount)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        receipt.store(receipt_account)?;
+    }
+    vault.store(deposit_account)
+}
+
+// Load a vault and ensure the withdrawing authority signed and matches it
+fn load_authorized_vault(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    authority: &AccountInfo,
+) -> Result<VaultState, ProgramError> {
+    // Ensure the deposit account is a vault owned by the program
+    let vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+
+    // Only the recorded authority may withdraw
+    if !authority.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if vault.authority != *authority.key {
+        return Err(VaultError::InvalidAuthority.into());
+    }
+    Ok(vault)
+}
+
+// The guardian can pause either direction independently
+fn check_deposits_open(vault: &VaultState) -> ProgramResult {
+    if vault.deposits_paused {
+        return Err(VaultError::DepositsPaused.into());
+    }
+    Ok(())
+}
+
+fn check_withdrawals_open(vault: &VaultState) -> ProgramResult {
+    if vault.withdrawals_paused {
+        return Err(VaultError::WithdrawalsPaused.into());
+    }
+    Ok(())
+}
+
+// Ensure the guardian signed and matches the vault's configured guardian
+fn check_guardian(vault: &VaultState, guardian: &AccountInfo) -> ProgramResult {
+    if !guardian.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if !vault.has_guardian() || vault.guardian != *guardian.key {
+        return Err(VaultError::InvalidGuardian.into());
+    }
+    Ok(())
+}
+
+// Nothing leaves a vault before its unlock time
+fn check_unlocked(vault: &VaultState) -> ProgramResult {
+    if Clock::get()?.unix_timestamp < vault.unlock_at {
+        return Err(VaultError::VaultLocked.into());
+    }
+    Ok(())
+}
+
+// Zero and drop a program account's data so its discriminator can never be read
+// again, hand it back to the system program and send every lamport, including
+// the rent reserve, to the destination
+fn close_program_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    account.try_borrow_mut_data()?.fill(0);
+    account.realloc(0, false)?;
+    account.assign(&system_program::ID);
+    transfer_lamports(account, destination, account.lamports())
+}
+
+// Vaults with a withdrawal delay only pay out through RequestWithdrawal and
+// ExecuteWithdrawal
+fn check_immediate_withdrawal(vault: &VaultState) -> ProgramResult {
+    if vault.withdrawal_delay > 0 {
+        return Err(VaultError::WithdrawalDelayRequired.into());
+    }
+    Ok(())
+}
+
+// Multisig vaults only pay out through approved proposals
+fn check_single_authority(vault: &VaultState) -> ProgramResult {
+    if vault.has_multisig() {
+        return Err(VaultError::MultisigRequired.into());
+    }
+    Ok(())
+}
+
+// On a multisig vault the authority's own signature is not enough: the next
+// account must be the multisig, followed by `threshold` distinct members who
+// signed the same transaction. Returns the multisig account when there is one.
+fn check_multisig_cosigners<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    vault: &VaultState,
+    accounts_iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+) -> Result<Option<&'a AccountInfo<'b>>, ProgramError> {
+    if !vault.has_multisig() {
+        return Ok(None);
+    }
+    let multisig_account = next_account_info(accounts_iter)?;
+    let multisig = load_multisig(program_id, deposit_account, vault, multisig_account)?;
+
+    let mut approvals = 0u16;
+    for _ in 0..multisig.threshold {
+        let signer = next_account_info(accounts_iter)?;
+        if !signer.is_signer {
+            return Err(VaultError::MissingSigner.into());
+        }
+        let index = multisig
+            .signer_index(signer.key)
+            .ok_or(VaultError::InvalidMultisigSigner)?;
+        let bit = 1u16 << index;
+        if approvals & bit != 0 {
+            return Err(VaultError::AlreadyApproved.into());
+        }
+        approvals |= bit;
+    }
+    Ok(Some(multisig_account))
+}
+
+// Load the vault's allowlist, which callers pass right after their own accounts
+fn load_allowlist(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    vault: &VaultState,
+    allowlist_account: &AccountInfo,
+) -> Result<RecipientAllowlist, ProgramError> {
+    if vault.allowlist != *allowlist_account.key {
+        return Err(VaultError::InvalidAllowlist.into());
+    }
+    let allowlist = RecipientAllowlist::load_account(allowlist_account, program_id)?;
+    if allowlist.vault != *deposit_account.key {
+        return Err(VaultError::InvalidAllowlist.into());
+    }
+    Ok(allowlist)
+}
+
+// When the vault has an allowlist, the next account must be it and the
+// recipient must be on it and active
+fn check_recipient_allowed<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    vault: &VaultState,
+    accounts_iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+    recipient: &Pubkey,
+) -> ProgramResult {
+    if !vault.has_allowlist() {
+        return Ok(());
+    }
+    let allowlist_account = next_account_info(accounts_iter)?;
+    let allowlist = load_allowlist(program_id, deposit_account, vault, allowlist_account)?;
+    if !allowlist.is_allowed(recipient, Clock::get()?.unix_timestamp) {
+        return Err(VaultError::RecipientNotAllowed.into());
+    }
+    Ok(())
+}
+
+// Vesting vaults only take the funder's initial deposit and only pay out
+// through Claim and Revoke
+fn check_not_vesting(vault: &VaultState) -> ProgramResult {
+    if vault.has_vesting() {
+        return Err(VaultError::VestingEnabled.into());
+    }
+    Ok(())
+}
 
+// Enforce the unlock time and the cooldown between authority withdrawals, and
+// start the next cooldown
+fn check_withdrawal_timing(vault: &mut VaultState) -> ProgramResult {
+    check_unlocked(vault)?;
+    let now = Clock::get()?.unix_timestamp;
+    if vault.last_withdrawal_at > 0 && now < vault.next_withdrawal_at() {
+        return Err(VaultError::WithdrawalCooldown.into());
+    }
+    vault.last_withdrawal_at = now;
     Ok(())
 }
 
+// Pay lamports out of the vault and record the withdrawal
+fn pay_out(
+    vault: &mut VaultState,
+    deposit_account: &AccountInfo,
+    recipient: &AccountInfo,
+    amount: u64,
+) -> ProgramResult {
+    transfer_lamports(deposit_account, recipient, amount)?;
+
+    vault.total_withdrawn = vault
+        .total_withdrawn
+        .checked_add(amount)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
 pub fn withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let accounts_iter = &mut accounts.iter();
     let deposit_account = next_account_info(accounts_iter)?;
     let recipient = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
 
-    // Ensure the deposit account is owned by the program
-    if deposit_account.owner != program_id {
-        return Err(ProgramError::IncorrectProgramId);
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    // Paying the vault to itself would read and write the same balance
+    if recipient.key == deposit_account.key {
+        return Err(VaultError::InvalidRecipient.into());
     }
+    check_withdrawals_open(&vault)?;
+    check_single_authority(&vault)?;
+    check_not_vesting(&vault)?;
+    check_immediate_withdrawal(&vault)?;
+    check_recipient_allowed(
+        program_id,
+        deposit_account,
+        &vault,
+        accounts_iter,
+        recipient.key,
+    )?;
+    check_withdrawal_timing(&mut vault)?;
 
-    let mut deposit_data = deposit_account.try_borrow_mut_data()?;
-    let mut total_deposited = u64::from_le_bytes(deposit_data[..8].try_into().unwrap());
-    let withdrawal_amount = total_deposited / WITHDRAWAL_PERCENTAGE;
+    let withdrawal_amount = vault.policy_amount()?;
 
     if withdrawal_amount == 0 {
-        return Err(ProgramError::InsufficientFunds);
+        return Err(VaultError::WithdrawalTooSmall.into());
+    }
+
+    // Calculate the withdrawal amount, never touching the rent-exempt reserve
+    let available = available_lamports(deposit_account)?;
+    if available == 0 {
+        return Err(VaultError::InsufficientVaultFunds.into());
     }
+    let withdrawal_amount = std::cmp::min(withdrawal_amount, available);
+
+    vault.record_outflow(withdrawal_amount, Clock::get()?.unix_timestamp)?;
+    pay_out(&mut vault, deposit_account, recipient, withdrawal_amount)
+}
 
-    // Calculate the withdrawal amount
-    let withdrawal_amount = std::cmp::min(withdrawal_amount, **deposit_account.lamports.borrow());
+pub fn withdraw_amount(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let recipient = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
 
-    // Transfer the withdrawal amount
-    **deposit_account.try_borrow_mut_lamports()? -= withdrawal_amount;
-    **recipient.try_borrow_mut_lamports()? += withdrawal_amount;
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    // Paying the vault to itself would read and write the same balance
+    if recipient.key == deposit_account.key {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+    check_withdrawals_open(&vault)?;
+    check_single_authority(&vault)?;
+    check_not_vesting(&vault)?;
+    check_immediate_withdrawal(&vault)?;
+    check_recipient_allowed(
+        program_id,
+        deposit_account,
+        &vault,
+        accounts_iter,
+        recipient.key,
+    )?;
+    check_withdrawal_timing(&mut vault)?;
 
-    // Update the total deposited amount
-    total_deposited -= withdrawal_amount;
-    deposit_data[..8].copy_from_slice(&total_deposited.to_le_bytes());
+    if amount == 0 {
+        return Err(VaultError::WithdrawalTooSmall.into());
+    }
 
-    Ok(())
+    // The exact amount must be covered by both the recorded balance and the
+    // lamports held above the rent-exempt reserve
+    let available = std::cmp::min(
+        vault.authority_balance()?,
+        available_lamports(deposit_account)?,
+    );
+    if amount > available {
+        return Err(VaultError::InsufficientVaultFunds.into());
+    }
+
+    vault.record_outflow(amount, Clock::get()?.unix_timestamp)?;
+    pay_out(&mut vault, deposit_account, recipient, amount)
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub enum TransferInstruction {
-    DepositInstruction(u64),
-    WithdrawalInstruction,
+pub fn close_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let destination = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+
+    let vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_withdrawals_open(&vault)?;
+    check_unlocked(&vault)?;
+    let multisig_account =
+        check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+
+    if destination.key == deposit_account.key {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+    // Depositors must redeem, pending withdrawals and streams must settle and
+    // every token balance must be withdrawn before the vault can be shut down
+    if vault.outstanding_principal > 0
+        || vault.pending_withdrawals > 0
+        || vault.active_streams > 0
+        || vault.token_balances > 0
+    {
+        return Err(VaultError::OutstandingObligations.into());
+    }
+    if vault.has_shares() {
+        let share_mint = next_account_info(accounts_iter)?;
+        if share_supply(&vault, share_mint)? > 0 {
+            return Err(VaultError::OutstandingObligations.into());
+        }
+    }
+    // A vesting vault closes only once everything has been claimed or revoked.
+    // The schedule closes with it and its rent goes back to the funder.
+    let vesting_accounts = if vault.has_vesting() {
+        let vesting_account = next_account_info(accounts_iter)?;
+        let funder = next_account_info(accounts_iter)?;
+        let vesting = load_vesting(program_id, deposit_account, &vault, vesting_account)?;
+        if vault.balance()? > 0 {
+            return Err(VaultError::OutstandingObligations.into());
+        }
+        if *funder.key != vesting.funder {
+            return Err(VaultError::InvalidVesting.into());
+        }
+        Some((vesting_account, funder))
+    } else {
+        None
+    };
+    // The allowlist closes with the vault, so a vault re-initialized at the
+    // same address can create a fresh one
+    let allowlist_account = if vault.has_allowlist() {
+        let allowlist_account = next_account_info(accounts_iter)?;
+        let allowlist = load_allowlist(program_id, deposit_account, &vault, allowlist_account)?;
+        if !allowlist.is_allowed(destination.key, Clock::get()?.unix_timestamp) {
+            return Err(VaultError::RecipientNotAllowed.into());
+        }
+        Some(allowlist_account)
+    } else {
+        None
+    };
+
+    // The multisig goes with the vault, but not while a proposal still holds
+    // its proposer's rent
+    if let Some(multisig_account) = multisig_account {
+        let multisig = load_multisig(program_id, deposit_account, &vault, multisig_account)?;
+        if multisig.open_proposals > 0 {
+            return Err(VaultError::OutstandingObligations.into());
+        }
+        close_program_account(multisig_account, destination)?;
+    }
+    if let Some(allowlist_account) = allowlist_account {
+        close_program_account(allowlist_account, destination)?;
+    }
+    if let Some((vesting_account, funder)) = vesting_accounts {
+        close_program_account(vesting_account, funder)?;
+    }
+    close_program_account(deposit_account, destination)
 }
 
-pub fn process_instruction(
+pub fn update_policy(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    input: &[u8],
+    withdrawal_bps: u16,
 ) -> ProgramResult {
-    let instruction = TransferInstruction::try_from_slice(input)?;
-    match instruction {
-        TransferInstruction::DepositInstruction(amount) => deposit(program_id, accounts, amount),
-        TransferInstruction::WithdrawalInstruction => withdraw(program_id, accounts),
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+    validate_withdrawal_bps(withdrawal_bps)?;
+    // Under a rate limit the policy can only be lowered, so a compromised
+    // authority cannot raise it to drain a mint in one withdrawal
+    if vault.rate_limit_window > 0 && withdrawal_bps > vault.withdrawal_bps {
+        return Err(VaultError::RateLimitCannotBeLoosened.into());
+    }
+
+    vault.withdrawal_bps = withdrawal_bps;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+// Set the unlock time and the minimum interval between withdrawals. A lock can
+// only ever be tightened, otherwise the authority could simply lift it.
+pub fn set_withdrawal_lock(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    withdrawal_interval: i64,
+    unlock_at: i64,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+
+    if withdrawal_interval < 0 || unlock_at < 0 {
+        return Err(VaultError::InvalidLock.into());
+    }
+    if withdrawal_interval < vault.withdrawal_interval || unlock_at < vault.unlock_at {
+        return Err(VaultError::LockCannotBeShortened.into());
+    }
+
+    vault.withdrawal_interval = withdrawal_interval;
+    vault.unlock_at = unlock_at;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+// Configure the outflow rate limit. Like the withdrawal lock it can only be
+// tightened, so a compromised authority cannot lift it before draining the vault.
+pub fn set_rate_limit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    window: i64,
+    max_lamports: u64,
+    max_bps: u16,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+
+    if window <= 0 || (max_lamports == 0 && max_bps == 0) || max_bps > MAX_BPS {
+        return Err(VaultError::InvalidRateLimit.into());
+    }
+    if vault.rate_limit_window > 0 {
+        let loosens_lamports = vault.rate_limit_lamports > 0
+            && (max_lamports == 0 || max_lamports > vault.rate_limit_lamports);
+        let loosens_bps =
+            vault.rate_limit_bps > 0 && (max_bps == 0 || max_bps > vault.rate_limit_bps);
+        if window < vault.rate_limit_window || loosens_lamports || loosens_bps {
+            return Err(VaultError::RateLimitCannotBeLoosened.into());
+        }
+    }
+
+    vault.rate_limit_window = window;
+    vault.rate_limit_lamports = max_lamports;
+    vault.rate_limit_bps = max_bps;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+// Tighten-only, like the withdrawal lock
+pub fn set_withdrawal_delay(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    withdrawal_delay: i64,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+
+    if withdrawal_delay < 0 {
+        return Err(VaultError::InvalidLock.into());
+    }
+    if withdrawal_delay < vault.withdrawal_delay {
+        return Err(VaultError::LockCannotBeShortened.into());
+    }
+    // Tokens only leave through WithdrawToken, which a delay rules out
+    if withdrawal_delay > 0 && vault.token_balances > 0 {
+        return Err(VaultError::OutstandingObligations.into());
+    }
+
+    vault.withdrawal_delay = withdrawal_delay;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+// Load a pending withdrawal and make sure it belongs to this vault
+fn load_pending_withdrawal(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    pending_account: &AccountInfo,
+) -> Result<PendingWithdrawal, ProgramError> {
+    let pending = PendingWithdrawal::load_account(pending_account, program_id)?;
+    if pending.vault != *deposit_account.key {
+        return Err(VaultError::InvalidPendingWithdrawal.into());
+    }
+    let expected = Pubkey::create_program_address(
+        &[
+            WITHDRAWAL_SEED,
+            deposit_account.key.as_ref(),
+            &pending.id.to_le_bytes(),
+            &[pending.bump],
+        ],
+        program_id,
+    )
+    .map_err(|_| VaultError::InvalidPendingWithdrawal)?;
+    if expected != *pending_account.key {
+        return Err(VaultError::InvalidPendingWithdrawal.into());
+    }
+    Ok(pending)
+}
+
+pub fn request_withdrawal(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    recipient: Pubkey,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let payer = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let pending_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    check_funding_accounts(payer, deposit_account, system_program)?;
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_withdrawals_open(&vault)?;
+    check_single_authority(&vault)?;
+    check_not_vesting(&vault)?;
+
+    if amount == 0 {
+        return Err(VaultError::WithdrawalTooSmall.into());
+    }
+    if amount > vault.authority_balance()? {
+        return Err(VaultError::InsufficientVaultFunds.into());
+    }
+    if recipient == *deposit_account.key {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+    check_recipient_allowed(
+        program_id,
+        deposit_account,
+        &vault,
+        accounts_iter,
+        &recipient,
+    )?;
+
+    let id = vault.next_withdrawal_id;
+    let (expected, bump) = find_pending_withdrawal_address(deposit_account.key, id, program_id);
+    if expected != *pending_account.key {
+        return Err(VaultError::InvalidPendingWithdrawal.into());
+    }
+    create_pda_account(
+        payer,
+        pending_account,
+        system_program,
+        program_id,
+        PendingWithdrawal::LEN,
+        &[
+            WITHDRAWAL_SEED,
+            deposit_account.key.as_ref(),
+            &id.to_le_bytes(),
+            &[bump],
+        ],
+    )?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let executable_at = now
+        .checked_add(vault.withdrawal_delay)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    PendingWithdrawal::new(
+        *deposit_account.key,
+        id,
+        bump,
+        amount,
+        recipient,
+        *payer.key,
+        now,
+        executable_at,
+    )
+    .store(pending_account)?;
+
+    vault.next_withdrawal_id = id.checked_add(1).ok_or(VaultError::ArithmeticOverflow)?;
+    vault.pending_withdrawals = vault
+        .pending_withdrawals
+        .checked_add(1)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    vault.last_activity_at = now;
+    vault.store(deposit_account)
+}
+
+// Anyone may execute a matured withdrawal; the recipient was fixed at request time
+pub fn execute_withdrawal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let pending_account = next_account_info(accounts_iter)?;
+    let recipient = next_account_info(accounts_iter)?;
+    let rent_destination = next_account_info(accounts_iter)?;
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    check_withdrawals_open(&vault)?;
+    check_single_authority(&vault)?;
+    check_not_vesting(&vault)?;
+    let pending = load_pending_withdrawal(program_id, deposit_account, pending_account)?;
+
+    if *recipient.key != pending.recipient {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+    check_recipient_allowed(
+        program_id,
+        deposit_account,
+        &vault,
+        accounts_iter,
+        recipient.key,
+    )?;
+    if *rent_destination.key != pending.rent_payer {
+        return Err(VaultError::InvalidPendingWithdrawal.into());
+    }
+    if Clock::get()?.unix_timestamp < pending.executable_at {
+        return Err(VaultError::WithdrawalNotMature.into());
+    }
+    check_withdrawal_timing(&mut vault)?;
+
+    let available = std::cmp::min(
+        vault.authority_balance()?,
+        available_lamports(deposit_account)?,
+    );
+    if pending.amount > available {
+        return Err(VaultError::InsufficientVaultFunds.into());
+    }
+
+    vault.record_outflow(pending.amount, Clock::get()?.unix_timestamp)?;
+    vault.pending_withdrawals = vault
+        .pending_withdrawals
+        .checked_sub(1)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    pay_out(&mut vault, deposit_account, recipient, pending.amount)?;
+
+    close_program_account(pending_account, rent_destination)
+}
+
+// Either the authority or the guardian may cancel a pending withdrawal
+pub fn cancel_withdrawal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let pending_account = next_account_info(accounts_iter)?;
+    let signer = next_account_info(accounts_iter)?;
+    let rent_destination = next_account_info(accounts_iter)?;
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    if !signer.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if vault.authority != *signer.key {
+        check_guardian(&vault, signer)?;
+    }
+    let pending = load_pending_withdrawal(program_id, deposit_account, pending_account)?;
+
+    if *rent_destination.key != pending.rent_payer {
+        return Err(VaultError::InvalidPendingWithdrawal.into());
+    }
+
+    vault.pending_withdrawals = vault
+        .pending_withdrawals
+        .checked_sub(1)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)?;
+
+    close_program_account(pending_account, rent_destination)
+}
+
+// The default pubkey removes the guardian. Once a guardian is set it must
+// co-sign any change, so a compromised authority cannot remove it.
+pub fn set_guardian(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    guardian: Pubkey,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    if vault.has_guardian() {
+        check_guardian(&vault, next_account_info(accounts_iter)?)?;
+    }
+    check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+
+    if guardian == *deposit_account.key {
+        return Err(VaultError::InvalidGuardian.into());
+    }
+
+    vault.guardian = guardian;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+// Only the guardian can pause or resume; it never moves funds itself
+pub fn set_pause(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    deposits_paused: bool,
+    withdrawals_paused: bool,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let guardian = next_account_info(accounts_iter)?;
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    check_guardian(&vault, guardian)?;
+
+    vault.deposits_paused = deposits_paused;
+    vault.withdrawals_paused = withdrawals_paused;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+// Hand withdrawals over to an M-of-N signer set. This is one-way: the
+// authority can no longer withdraw on its own afterwards, and every change to
+// the vault's settings, guardian, streams or closing needs `threshold` members
+// to co-sign.
+pub fn create_multisig(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    threshold: u8,
+    signers: Vec<Pubkey>,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let payer = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let multisig_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    check_funding_accounts(payer, deposit_account, system_program)?;
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    if vault.has_multisig() {
+        return Err(VaultError::AlreadyInitialized.into());
+    }
+    // Withdrawals and streams the authority already set up would still pay
+    // out without any approvals, and tokens could no longer leave at all
+    if vault.pending_withdrawals > 0 || vault.active_streams > 0 || vault.token_balances > 0 {
+        return Err(VaultError::OutstandingObligations.into());
+    }
+
+    let (expected, bump) = find_multisig_address(deposit_account.key, program_id);
+    if expected != *multisig_account.key {
+        return Err(VaultError::InvalidMultisig.into());
+    }
+    let multisig = MultisigConfig::new(*deposit_account.key, bump, threshold, &signers)?;
+    create_pda_account(
+        payer,
+        multisig_account,
+        system_program,
+        program_id,
+        MultisigConfig::LEN,
+        &[MULTISIG_SEED, deposit_account.key.as_ref(), &[bump]],
+    )?;
+    multisig.store(multisig_account)?;
+
+    vault.multisig = *multisig_account.key;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+// Load the multisig recorded on the vault
+fn load_multisig(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    vault: &VaultState,
+    multisig_account: &AccountInfo,
+) -> Result<MultisigConfig, ProgramError> {
+    if !vault.has_multisig() || vault.multisig != *multisig_account.key {
+        return Err(VaultError::InvalidMultisig.into());
+    }
+    let multisig = MultisigConfig::load_account(multisig_account, program_id)?;
+    if multisig.vault != *deposit_account.key {
+        return Err(VaultError::InvalidMultisig.into());
+    }
+    Ok(multisig)
+}
+
+// Load a proposal and make sure it belongs to this multisig
+fn load_proposal(
+    program_id: &Pubkey,
+    multisig_account: &AccountInfo,
+    proposal_account: &AccountInfo,
+) -> Result<WithdrawalProposal, ProgramError> {
+    let proposal = WithdrawalProposal::load_account(proposal_account, program_id)?;
+    if proposal.multisig != *multisig_account.key {
+        return Err(VaultError::InvalidProposal.into());
+    }
+    let expected = Pubkey::create_program_address(
+        &[
+            PROPOSAL_SEED,
+            multisig_account.key.as_ref(),
+            &proposal.id.to_le_bytes(),
+            &[proposal.bump],
+        ],
+        program_id,
+    )
+    .map_err(|_| VaultError::InvalidProposal)?;
+    if expected != *proposal_account.key {
+        return Err(VaultError::InvalidProposal.into());
+    }
+    Ok(proposal)
+}
+
+// Record an approval from a multisig member, rejecting duplicates
+fn approve_proposal(
+    multisig: &MultisigConfig,
+    proposal: &mut WithdrawalProposal,
+    signer: &AccountInfo,
+) -> ProgramResult {
+    if !signer.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    let index = multisig
+        .signer_index(signer.key)
+        .ok_or(VaultError::InvalidMultisigSigner)?;
+    let bit = 1u16 << index;
+    if proposal.approvals & bit != 0 {
+        return Err(VaultError::AlreadyApproved.into());
+    }
+    proposal.approvals |= bit;
+    Ok(())
+}
+
+// Any member may propose; proposing counts as the proposer's approval
+pub fn propose_withdrawal(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    recipient: Pubkey,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let proposer = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let multisig_account = next_account_info(accounts_iter)?;
+    let proposal_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    check_payer(proposer, system_program)?;
+    if !multisig_account.is_writable {
+        return Err(VaultError::AccountNotWritable.into());
+    }
+    let vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    check_withdrawals_open(&vault)?;
+    check_not_vesting(&vault)?;
+    let mut multisig = load_multisig(program_id, deposit_account, &vault, multisig_account)?;
+
+    if amount == 0 {
+        return Err(VaultError::WithdrawalTooSmall.into());
+    }
+    if amount > vault.authority_balance()? {
+        return Err(VaultError::InsufficientVaultFunds.into());
+    }
+    if recipient == *deposit_account.key {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+    check_recipient_allowed(
+        program_id,
+        deposit_account,
+        &vault,
+        accounts_iter,
+        &recipient,
+    )?;
+
+    let id = multisig.next_proposal_id;
+    let (expected, bump) = find_proposal_address(multisig_account.key, id, program_id);
+    if expected != *proposal_account.key {
+        return Err(VaultError::InvalidProposal.into());
+    }
+    let mut proposal = WithdrawalProposal::new(
+        *multisig_account.key,
+        id,
+        bump,
+        amount,
+        recipient,
+        *proposer.key,
+        Clock::get()?.unix_timestamp,
+    );
+    approve_proposal(&multisig, &mut proposal, proposer)?;
+
+    create_pda_account(
+        proposer,
+        proposal_account,
+        system_program,
+        program_id,
+        WithdrawalProposal::LEN,
+        &[
+            PROPOSAL_SEED,
+            multisig_account.key.as_ref(),
+            &id.to_le_bytes(),
+            &[bump],
+        ],
+    )?;
+    proposal.store(proposal_account)?;
+
+    multisig.next_proposal_id = id.checked_add(1).ok_or(VaultError::ArithmeticOverflow)?;
+    multisig.open_proposals = multisig
+        .open_proposals
+        .checked_add(1)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    multisig.store(multisig_account)
+}
+
+pub fn approve(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let multisig_account = next_account_info(accounts_iter)?;
+    let proposal_account = next_account_info(accounts_iter)?;
+    let signer = next_account_info(accounts_iter)?;
+
+    let vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    let multisig = load_multisig(program_id, deposit_account, &vault, multisig_account)?;
+    let mut proposal = load_proposal(program_id, multisig_account, proposal_account)?;
+
+    approve_proposal(&multisig, &mut proposal, signer)?;
+    proposal.store(proposal_account)
+}
+
+// Anyone may execute a proposal once it has enough approvals. It goes through
+// the same checks as an authority withdrawal, with the withdrawal delay
+// counted from when it was proposed.
+pub fn execute_proposal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let multisig_account = next_account_info(accounts_iter)?;
+    let proposal_account = next_account_info(accounts_iter)?;
+    let recipient = next_account_info(accounts_iter)?;
+    let rent_destination = next_account_info(accounts_iter)?;
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    check_withdrawals_open(&vault)?;
+    check_not_vesting(&vault)?;
+    let mut multisig = load_multisig(program_id, deposit_account, &vault, multisig_account)?;
+    let proposal = load_proposal(program_id, multisig_account, proposal_account)?;
+
+    if *recipient.key != proposal.recipient {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+    check_recipient_allowed(
+        program_id,
+        deposit_account,
+        &vault,
+        accounts_iter,
+        recipient.key,
+    )?;
+    if *rent_destination.key != proposal.proposer {
+        return Err(VaultError::InvalidProposal.into());
+    }
+    if proposal.approval_count() < multisig.threshold {
+        return Err(VaultError::ThresholdNotReached.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    if now < proposal.created_at.saturating_add(vault.withdrawal_delay) {
+        return Err(VaultError::WithdrawalNotMature.into());
+    }
+    check_withdrawal_timing(&mut vault)?;
+
+    let available = std::cmp::min(
+        vault.authority_balance()?,
+        available_lamports(deposit_account)?,
+    );
+    if proposal.amount > available {
+        return Err(VaultError::InsufficientVaultFunds.into());
+    }
+
+    vault.record_outflow(proposal.amount, now)?;
+    pay_out(&mut vault, deposit_account, recipient, proposal.amount)?;
+
+    multisig.open_proposals = multisig
+        .open_proposals
+        .checked_sub(1)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    multisig.store(multisig_account)?;
+    close_program_account(proposal_account, rent_destination)
+}
+
+// The proposer can withdraw their proposal and the guardian can veto one
+pub fn cancel_proposal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let multisig_account = next_account_info(accounts_iter)?;
+    let proposal_account = next_account_info(accounts_iter)?;
+    let signer = next_account_info(accounts_iter)?;
+    let rent_destination = next_account_info(accounts_iter)?;
+
+    let vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    let mut multisig = load_multisig(program_id, deposit_account, &vault, multisig_account)?;
+    let proposal = load_proposal(program_id, multisig_account, proposal_account)?;
+
+    if !signer.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if proposal.proposer != *signer.key {
+        check_guardian(&vault, signer)?;
+    }
+    if *rent_destination.key != proposal.proposer {
+        return Err(VaultError::InvalidProposal.into());
+    }
+
+    multisig.open_proposals = multisig
+        .open_proposals
+        .checked_sub(1)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    multisig.store(multisig_account)?;
+    close_program_account(proposal_account, rent_destination)
+}
+
+// Added recipients only become payable once the vault's withdrawal delay has
+// passed, so a compromised authority cannot add and pay itself in one go
+pub fn add_recipient(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    recipient: Pubkey,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let payer = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let allowlist_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    check_funding_accounts(payer, deposit_account, system_program)?;
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+
+    if recipient == *deposit_account.key || recipient == Pubkey::default() {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+
+    // The first recipient creates the allowlist and turns it on for good
+    let mut allowlist = if vault.has_allowlist() {
+        load_allowlist(program_id, deposit_account, &vault, allowlist_account)?
+    } else {
+        let (expected, bump) = find_allowlist_address(deposit_account.key, program_id);
+        if expected != *allowlist_account.key {
+            return Err(VaultError::InvalidAllowlist.into());
+        }
+        create_pda_account(
+            payer,
+            allowlist_account,
+            system_program,
+            program_id,
+            RecipientAllowlist::LEN,
+            &[ALLOWLIST_SEED, deposit_account.key.as_ref(), &[bump]],
+        )?;
+        vault.allowlist = *allowlist_account.key;
+        RecipientAllowlist::new(*deposit_account.key, bump)
+    };
+
+    let now = Clock::get()?.unix_timestamp;
+    let active_at = now
+        .checked_add(vault.withdrawal_delay)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    allowlist.add(recipient, active_at)?;
+    allowlist.store(allowlist_account)?;
+
+    vault.last_activity_at = now;
+    vault.store(deposit_account)
+}
+
+// Removals take effect immediately
+pub fn remove_recipient(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    recipient: Pubkey,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let allowlist_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+    let mut allowlist = load_allowlist(program_id, deposit_account, &vault, allowlist_account)?;
+
+    allowlist.remove(&recipient)?;
+    allowlist.store(allowlist_account)?;
+
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+// Authority transfers take two steps: the current authority proposes a key and
+// that key must sign AcceptAuthority, so control never moves to an address
+// nobody holds
+pub fn propose_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_authority: Pubkey,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+
+    if new_authority == Pubkey::default() || new_authority == vault.authority {
+        return Err(VaultError::InvalidAuthority.into());
+    }
+
+    vault.pending_authority = new_authority;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+pub fn accept_authority(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let new_authority = next_account_info(accounts_iter)?;
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+
+    if vault.pending_authority == Pubkey::default() {
+        return Err(VaultError::NoPendingAuthority.into());
+    }
+    if !new_authority.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if vault.pending_authority != *new_authority.key {
+        return Err(VaultError::InvalidAuthority.into());
+    }
+
+    vault.authority = vault.pending_authority;
+    vault.pending_authority = Pubkey::default();
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+pub fn cancel_authority_transfer(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+
+    if vault.pending_authority == Pubkey::default() {
+        return Err(VaultError::NoPendingAuthority.into());
+    }
+
+    vault.pending_authority = Pubkey::default();
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+// Turn an empty vault into a vesting vault and fund it in one step. The payer
+// is recorded as the funder, who may revoke unvested funds if allowed.
+#[allow(clippy::too_many_arguments)]
+pub fn create_vesting(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    beneficiary: Pubkey,
+    amount: u64,
+    start: i64,
+    cliff: i64,
+    end: i64,
+    interval: i64,
+    revocable: bool,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let funder = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let vesting_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    check_funding_accounts(funder, deposit_account, system_program)?;
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+    check_deposits_open(&vault)?;
+
+    if vault.has_vesting() {
+        return Err(VaultError::AlreadyInitialized.into());
+    }
+    // Existing funds would otherwise end up in the schedule
+    if vault.balance()? > 0
+        || vault.outstanding_principal > 0
+        || vault.active_streams > 0
+        || vault.has_shares()
+    {
+        return Err(VaultError::OutstandingObligations.into());
+    }
+    if beneficiary == Pubkey::default() || beneficiary == *deposit_account.key {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+
+    let (expected, bump) = find_vesting_address(deposit_account.key, program_id);
+    if expected != *vesting_account.key {
+        return Err(VaultError::InvalidVesting.into());
+    }
+    let vesting = VestingSchedule::new(
+        *deposit_account.key,
+        bump,
+        beneficiary,
+        *funder.key,
+        amount,
+        start,
+        cliff,
+        end,
+        interval,
+        revocable,
+    )?;
+    create_pda_account(
+        funder,
+        vesting_account,
+        system_program,
+        program_id,
+        VestingSchedule::LEN,
+        &[VESTING_SEED, deposit_account.key.as_ref(), &[bump]],
+    )?;
+    vesting.store(vesting_account)?;
+
+    invoke(
+        &system_instruction::transfer(funder.key, deposit_account.key, amount),
+        &[
+            funder.clone(),
+            deposit_account.clone(),
+            system_program.clone(),
+        ],
+    )?;
+
+    vault.vesting = *vesting_account.key;
+    vault.total_deposited = vault
+        .total_deposited
+        .checked_add(amount)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+// Load the vesting schedule recorded on the vault
+fn load_vesting(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    vault: &VaultState,
+    vesting_account: &AccountInfo,
+) -> Result<VestingSchedule, ProgramError> {
+    if !vault.has_vesting() || vault.vesting != *vesting_account.key {
+        return Err(VaultError::InvalidVesting.into());
+    }
+    let vesting = VestingSchedule::load_account(vesting_account, program_id)?;
+    if vesting.vault != *deposit_account.key {
+        return Err(VaultError::InvalidVesting.into());
+    }
+    Ok(vesting)
+}
+
+// Anyone may push vested funds to the beneficiary
+pub fn claim(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let vesting_account = next_account_info(accounts_iter)?;
+    let beneficiary = next_account_info(accounts_iter)?;
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    check_withdrawals_open(&vault)?;
+    let mut vesting = load_vesting(program_id, deposit_account, &vault, vesting_account)?;
+
+    if *beneficiary.key != vesting.beneficiary {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+
+    let claimable = vesting.claimable(Clock::get()?.unix_timestamp)?;
+    if claimable == 0 {
+        return Err(VaultError::NothingToClaim.into());
+    }
+    if claimable > available_lamports(deposit_account)? {
+        return Err(VaultError::InsufficientVaultFunds.into());
+    }
+
+    vesting.claimed = vesting
+        .claimed
+        .checked_add(claimable)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    vesting.store(vesting_account)?;
+
+    pay_out(&mut vault, deposit_account, beneficiary, claimable)
+}
+
+// The funder takes back whatever has not vested yet; what has vested stays
+// claimable by the beneficiary
+pub fn revoke_vesting(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let vesting_account = next_account_info(accounts_iter)?;
+    let funder = next_account_info(accounts_iter)?;
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    check_withdrawals_open(&vault)?;
+    let mut vesting = load_vesting(program_id, deposit_account, &vault, vesting_account)?;
+
+    if !funder.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if *funder.key != vesting.funder {
+        return Err(VaultError::InvalidAuthority.into());
+    }
+    if !vesting.revocable || vesting.revoked {
+        return Err(VaultError::VestingNotRevocable.into());
+    }
+
+    let unvested = vesting.revoke(Clock::get()?.unix_timestamp)?;
+    vesting.store(vesting_account)?;
+
+    if unvested == 0 {
+        vault.last_activity_at = Clock::get()?.unix_timestamp;
+        return vault.store(deposit_account);
+    }
+    if unvested > available_lamports(deposit_account)? {
+        return Err(VaultError::InsufficientVaultFunds.into());
+    }
+    pay_out(&mut vault, deposit_account, funder, unvested)
+}
+
+pub fn create_stream(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    recipient: Pubkey,
+    rate: u64,
+    start: i64,
+    stop: i64,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let payer = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let stream_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    check_funding_accounts(payer, deposit_account, system_program)?;
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_withdrawals_open(&vault)?;
+    check_immediate_withdrawal(&vault)?;
+    check_single_authority(&vault)?;
+    check_not_vesting(&vault)?;
+    if vault.has_shares() {
+        return Err(VaultError::SharesEnabled.into());
+    }
+
+    if recipient == Pubkey::default() || recipient == *deposit_account.key {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+    check_recipient_allowed(
+        program_id,
+        deposit_account,
+        &vault,
+        accounts_iter,
+        &recipient,
+    )?;
+
+    let (expected, bump) = find_stream_address(deposit_account.key, &recipient, program_id);
+    if expected != *stream_account.key {
+        return Err(VaultError::InvalidStream.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    let stream = PayoutStream::new(
+        *deposit_account.key,
+        bump,
+        recipient,
+        *payer.key,
+        rate,
+        start,
+        stop,
+        now,
+    )?;
+    create_pda_account(
+        payer,
+        stream_account,
+        system_program,
+        program_id,
+        PayoutStream::LEN,
+        &[
+            STREAM_SEED,
+            deposit_account.key.as_ref(),
+            recipient.as_ref(),
+            &[bump],
+        ],
+    )?;
+    stream.store(stream_account)?;
+
+    vault.active_streams = vault
+        .active_streams
+        .checked_add(1)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    vault.last_activity_at = now;
+    vault.store(deposit_account)
+}
+
+// Load a stream and make sure it belongs to this vault
+fn load_stream(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    stream_account: &AccountInfo,
+) -> Result<PayoutStream, ProgramError> {
+    let stream = PayoutStream::load_account(stream_account, program_id)?;
+    if stream.vault != *deposit_account.key {
+        return Err(VaultError::InvalidStream.into());
+    }
+    let expected = Pubkey::create_program_address(
+        &[
+            STREAM_SEED,
+            deposit_account.key.as_ref(),
+            stream.recipient.as_ref(),
+            &[stream.bump],
+        ],
+        program_id,
+    )
+    .map_err(|_| VaultError::InvalidStream)?;
+    if expected != *stream_account.key {
+        return Err(VaultError::InvalidStream.into());
+    }
+    Ok(stream)
+}
+
+// Anyone may crank a stream. It pays whatever has accrued, as far as the
+// vault can cover it, and closes the stream once it has been paid in full.
+pub fn settle_stream(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let stream_account = next_account_info(accounts_iter)?;
+    let recipient = next_account_info(accounts_iter)?;
+    let rent_destination = next_account_info(accounts_iter)?;
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    check_withdrawals_open(&vault)?;
+    check_not_vesting(&vault)?;
+    check_unlocked(&vault)?;
+    let mut stream = load_stream(program_id, deposit_account, stream_account)?;
+
+    if *recipient.key != stream.recipient {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+    if *rent_destination.key != stream.rent_payer {
+        return Err(VaultError::InvalidStream.into());
+    }
+    check_recipient_allowed(
+        program_id,
+        deposit_account,
+        &vault,
+        accounts_iter,
+        recipient.key,
+    )?;
+
+    // Settle only what the rate limit still allows in this window; the rest
+    // stays accrued for a later settlement
+    let now = Clock::get()?.unix_timestamp;
+    let available = std::cmp::min(
+        vault.authority_balance()?,
+        available_lamports(deposit_account)?,
+    )
+    .min(vault.outflow_headroom(now)?);
+    let paid = stream.settle(now, available)?;
+    if paid == 0 && !stream.is_finished() {
+        return Err(VaultError::NothingToSettle.into());
+    }
+
+    vault.record_outflow(paid, now)?;
+    if stream.is_finished() {
+        vault.active_streams = vault
+            .active_streams
+            .checked_sub(1)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+    }
+    if paid > 0 {
+        pay_out(&mut vault, deposit_account, recipient, paid)?;
+    } else {
+        vault.store(deposit_account)?;
+    }
+
+    if stream.is_finished() {
+        return close_program_account(stream_account, rent_destination);
+    }
+    stream.store(stream_account)
+}
+
+// Stop a stream early and close it. The recipient is paid what has accrued
+// so far, as far as the vault could settle it right now, and forfeits the
+// rest. Either the authority or the guardian may cancel.
+pub fn cancel_stream(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let stream_account = next_account_info(accounts_iter)?;
+    let signer = next_account_info(accounts_iter)?;
+    let recipient = next_account_info(accounts_iter)?;
+    let rent_destination = next_account_info(accounts_iter)?;
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    if !signer.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if vault.authority == *signer.key {
+        check_multisig_cosigners(program_id, deposit_account, &vault, accounts_iter)?;
+    } else {
+        check_guardian(&vault, signer)?;
+    }
+    let mut stream = load_stream(program_id, deposit_account, stream_account)?;
+
+    if *recipient.key != stream.recipient {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+    if *rent_destination.key != stream.rent_payer {
+        return Err(VaultError::InvalidStream.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    let allowed = if vault.has_allowlist() {
+        let allowlist_account = next_account_info(accounts_iter)?;
+        load_allowlist(program_id, deposit_account, &vault, allowlist_account)?
+            .is_allowed(recipient.key, now)
+    } else {
+        true
+    };
+
+    // Nothing is payable while SettleStream would be refused
+    let payable = allowed && !vault.withdrawals_paused && now >= vault.unlock_at;
+    let available = if payable {
+        std::cmp::min(
+            vault.authority_balance()?,
+            available_lamports(deposit_account)?,
+        )
+        .min(vault.outflow_headroom(now)?)
+    } else {
+        0
+    };
+    let paid = stream.settle(now, available)?;
+
+    vault.record_outflow(paid, now)?;
+    vault.active_streams = vault
+        .active_streams
+        .checked_sub(1)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    if paid > 0 {
+        pay_out(&mut vault, deposit_account, recipient, paid)?;
+    } else {
+        vault.last_activity_at = now;
+        vault.store(deposit_account)?;
+    }
+    close_program_account(stream_account, rent_destination)
+}
+
+// The payer funds a new escrow for the payee in one step
+#[allow(clippy::too_many_arguments)]
+pub fn create_escrow(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    payee: Pubkey,
+    arbiter: Pubkey,
+    seed: u64,
+    amount: u64,
+    expires_at: i64,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let payer = next_account_info(accounts_iter)?;
+    let escrow_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    check_payer(payer, system_program)?;
+    if !escrow_account.is_writable {
+        return Err(VaultError::AccountNotWritable.into());
+    }
+
+    if amount == 0 {
+        return Err(VaultError::DepositTooSmall.into());
+    }
+    if payee == Pubkey::default() || payee == *payer.key || payee == *escrow_account.key {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+    if arbiter == *payer.key || arbiter == payee {
+        return Err(VaultError::InvalidEscrow.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    if expires_at <= now {
+        return Err(VaultError::EscrowExpired.into());
+    }
+
+    let (expected, bump) = find_escrow_address(payer.key, &payee, seed, program_id);
+    if expected != *escrow_account.key {
+        return Err(VaultError::InvalidEscrow.into());
+    }
+    if !escrow_account.data_is_empty() {
+        return Err(VaultError::AlreadyInitialized.into());
+    }
+    create_pda_account(
+        payer,
+        escrow_account,
+        system_program,
+        program_id,
+        EscrowState::LEN,
+        &[
+            ESCROW_SEED,
+            payer.key.as_ref(),
+            payee.as_ref(),
+            &seed.to_le_bytes(),
+            &[bump],
+        ],
+    )?;
+
+    invoke(
+        &system_instruction::transfer(payer.key, escrow_account.key, amount),
+        &[
+            payer.clone(),
+            escrow_account.clone(),
+            system_program.clone(),
+        ],
+    )?;
+
+    EscrowState::new(
+        *payer.key, payee, arbiter, seed, bump, amount, expires_at, now,
+    )
+    .store(escrow_account)
+}
+
+// Load an escrow and re-derive its address from the parties and seed
+fn load_escrow(
+    program_id: &Pubkey,
+    escrow_account: &AccountInfo,
+) -> Result<EscrowState, ProgramError> {
+    let escrow = EscrowState::load_account(escrow_account, program_id)?;
+    let expected = Pubkey::create_program_address(
+        &[
+            ESCROW_SEED,
+            escrow.payer.as_ref(),
+            escrow.payee.as_ref(),
+            &escrow.seed.to_le_bytes(),
+            &[escrow.bump],
+        ],
+        program_id,
+    )
+    .map_err(|_| VaultError::InvalidEscrow)?;
+    if expected != *escrow_account.key {
+        return Err(VaultError::InvalidEscrow.into());
+    }
+    Ok(escrow)
+}
+
+// Move an escrow into a settled state and pay its amount to the party it
+// settled in favor of
+fn settle_escrow(
+    escrow: &mut EscrowState,
+    escrow_account: &AccountInfo,
+    destination: &AccountInfo,
+    status: EscrowStatus,
+) -> ProgramResult {
+    let expected = match status {
+        EscrowStatus::Released => escrow.payee,
+        EscrowStatus::Refunded => escrow.payer,
+        EscrowStatus::Funded | EscrowStatus::Disputed => {
+            return Err(VaultError::InvalidEscrowState.into())
+        }
+    };
+    if *destination.key != expected {
+        return Err(VaultError::InvalidRecipient.into());
+    }
+
+    transfer_lamports(escrow_account, destination, escrow.amount)?;
+    escrow.status = status;
+    escrow.store(escrow_account)
+}
+
+// Funded -> Released: the payer approves payment to the payee
+pub fn release_escrow(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let escrow_account = next_account_info(accounts_iter)?;
+    let payer = next_account_info(accounts_iter)?;
+    let payee = next_account_info(accounts_iter)?;
+
+    let mut escrow = load_escrow(program_id, escrow_account)?;
+
+    if !payer.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if *payer.key != escrow.payer {
+        return Err(VaultError::InvalidAuthority.into());
+    }
+    if escrow.status != EscrowStatus::Funded {
+        return Err(VaultError::InvalidEscrowState.into());
+    }
+
+    settle_escrow(&mut escrow, escrow_account, payee, EscrowStatus::Released)
+}
+
+// Anyone may return an expired escrow, or a dispute the arbiter let time out,
+// to the payer
+pub fn refund_escrow(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let escrow_account = next_account_info(accounts_iter)?;
+    let payer = next_account_info(accounts_iter)?;
+
+    let mut escrow = load_escrow(program_id, escrow_account)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    match escrow.status {
+        EscrowStatus::Funded if now < escrow.expires_at => {
+            return Err(VaultError::EscrowNotExpired.into())
+        }
+        // An arbiter who never acts cannot lock the escrow for good
+        EscrowStatus::Disputed if now < escrow.dispute_deadline() => {
+            return Err(VaultError::DisputeNotTimedOut.into())
+        }
+        EscrowStatus::Funded | EscrowStatus::Disputed => {}
+        EscrowStatus::Released | EscrowStatus::Refunded => {
+            return Err(VaultError::InvalidEscrowState.into())
+        }
+    }
+
+    settle_escrow(&mut escrow, escrow_account, payer, EscrowStatus::Refunded)
+}
+
+// Funded -> Disputed: either party hands the decision to the arbiter. Only
+// possible before expiry, so the payee cannot block a due refund.
+pub fn dispute_escrow(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let escrow_account = next_account_info(accounts_iter)?;
+    let party = next_account_info(accounts_iter)?;
+
+    let mut escrow = load_escrow(program_id, escrow_account)?;
+
+    if !party.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if *party.key != escrow.payer && *party.key != escrow.payee {
+        return Err(VaultError::InvalidAuthority.into());
+    }
+    if !escrow.has_arbiter() {
+        return Err(VaultError::NoArbiter.into());
+    }
+    if escrow.status != EscrowStatus::Funded {
+        return Err(VaultError::InvalidEscrowState.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    if now >= escrow.expires_at {
+        return Err(VaultError::EscrowExpired.into());
+    }
+
+    escrow.status = EscrowStatus::Disputed;
+    escrow.disputed_at = now;
+    escrow.store(escrow_account)
+}
+
+// Disputed -> Released or Refunded: the arbiter decides
+pub fn resolve_escrow(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    release: bool,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let escrow_account = next_account_info(accounts_iter)?;
+    let arbiter = next_account_info(accounts_iter)?;
+    let destination = next_account_info(accounts_iter)?;
+
+    let mut escrow = load_escrow(program_id, escrow_account)?;
+
+    if !arbiter.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if !escrow.has_arbiter() || *arbiter.key != escrow.arbiter {
+        return Err(VaultError::InvalidAuthority.into());
+    }
+    if escrow.status != EscrowStatus::Disputed {
+        return Err(VaultError::InvalidEscrowState.into());
+    }
+
+    let status = if release {
+        EscrowStatus::Released
+    } else {
+        EscrowStatus::Refunded
+    };
+    settle_escrow(&mut escrow, escrow_account, destination, status)
+}
+
+// Once settled, the payer can reclaim the escrow account's rent
+pub fn close_escrow(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let escrow_account = next_account_info(accounts_iter)?;
+    let payer = next_account_info(accounts_iter)?;
+
+    let escrow = load_escrow(program_id, escrow_account)?;
+
+    if !payer.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    if *payer.key != escrow.payer {
+        return Err(VaultError::InvalidAuthority.into());
+    }
+    if !escrow.is_settled() {
+        return Err(VaultError::InvalidEscrowState.into());
+    }
+
+    close_program_account(escrow_account, payer)
+}
+
+pub fn redeem_deposit(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let receipt_account = next_account_info(accounts_iter)?;
+    let depositor = next_account_info(accounts_iter)?;
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    // Pausing applies, but not the withdrawal lock: it binds the authority and
+    // can be extended after depositors are in, so it must not trap them
+    check_withdrawals_open(&vault)?;
+
+    if !depositor.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+    let mut receipt = load_receipt(program_id, deposit_account, depositor, receipt_account)?;
+    if receipt.principal == 0 {
+        return Err(VaultError::NothingToRedeem.into());
+    }
+
+    // Pay back the principal, or when the vault holds less than it owes
+    // depositors, their share of what remains in proportion to the principal
+    // they still have in the vault; rounding favors the vault
+    let pool = std::cmp::min(vault.balance()?, vault.outstanding_principal);
+    let payout = mul_div(
+        receipt.principal,
+        pool as u128,
+        vault.outstanding_principal as u128,
+    )?;
+    if payout > available_lamports(deposit_account)? {
+        return Err(VaultError::InsufficientVaultFunds.into());
+    }
+
+    vault.outstanding_principal = vault
+        .outstanding_principal
+        .checked_sub(receipt.principal)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    receipt.principal = 0;
+    receipt.total_withdrawn = receipt
+        .total_withdrawn
+        .checked_add(payout)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    receipt.store(receipt_account)?;
+
+    pay_out(&mut vault, deposit_account, depositor, payout)
+}
+
+// Current supply of a vault's share mint
+fn share_supply(vault: &VaultState, share_mint: &AccountInfo) -> Result<u64, ProgramError> {
+    if *share_mint.key != vault.share_mint || *share_mint.owner != spl_token::id() {
+        return Err(VaultError::InvalidShareMint.into());
+    }
+    let data = share_mint.try_borrow_data()?;
+    Ok(StateWithExtensions::<Mint>::unpack(&data)?.base.supply)
+}
+
+pub fn enable_shares(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let payer = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let share_mint = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+    let token_program = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    check_funding_accounts(payer, deposit_account, system_program)?;
+    if *token_program.key != spl_token::id() {
+        return Err(VaultError::InvalidTokenProgram.into());
+    }
+
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    if vault.has_shares() {
+        return Err(VaultError::SharesEnabled.into());
+    }
+    // Shares must price every lamport in the vault, so they can only be
+    // switched on before anything has been deposited
+    if vault.balance()? > 0 || vault.outstanding_principal > 0 {
+        return Err(VaultError::OutstandingObligations.into());
+    }
+
+    let (expected, bump) = find_share_mint_address(deposit_account.key, program_id);
+    if expected != *share_mint.key {
+        return Err(VaultError::InvalidShareMint.into());
+    }
+
+    create_pda_account(
+        payer,
+        share_mint,
+        system_program,
+        token_program.key,
+        Mint::LEN,
+        &[SHARE_MINT_SEED, deposit_account.key.as_ref(), &[bump]],
+    )?;
+    invoke(
+        &spl_token::instruction::initialize_mint2(
+            token_program.key,
+            share_mint.key,
+            deposit_account.key,
+            None,
+            SHARE_DECIMALS,
+        )?,
+        &[share_mint.clone(), token_program.clone()],
+    )?;
+
+    vault.share_mint = *share_mint.key;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+pub fn deposit_for_shares(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let payer = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+    let share_mint = next_account_info(accounts_iter)?;
+    let share_account = next_account_info(accounts_iter)?;
+    let token_program = next_account_info(accounts_iter)?;
+
+    check_funding_accounts(payer, deposit_account, system_program)?;
+    if *token_program.key != spl_token::id() {
+        return Err(VaultError::InvalidTokenProgram.into());
+    }
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    check_deposits_open(&vault)?;
+    check_not_vesting(&vault)?;
+    if !vault.has_shares() {
+        return Err(VaultError::SharesNotEnabled.into());
+    }
+
+    // Price the deposit before it lands so it cannot move its own share price
+    let shares = vault.convert_to_shares(amount, share_supply(&vault, share_mint)?)?;
+    if shares == 0 {
+        return Err(VaultError::DepositTooSmall.into());
+    }
+
+    // Transfer the deposit amount
+    invoke(
+        &system_instruction::transfer(payer.key, deposit_account.key, amount),
+        &[
+            payer.clone(),
+            deposit_account.clone(),
+            system_program.clone(),
+        ],
+    )?;
+
+    // The vault is the mint authority, so it signs for the new shares
+    let bump = [vault.bump];
+    let signer_seeds: &[&[u8]] = &[VAULT_SEED, vault.owner.as_ref(), &bump];
+    invoke_signed(
+        &spl_token::instruction::mint_to(
+            token_program.key,
+            share_mint.key,
+            share_account.key,
+            deposit_account.key,
+            &[],
+            shares,
+        )?,
+        &[
+            share_mint.clone(),
+            share_account.clone(),
+            deposit_account.clone(),
+            token_program.clone(),
+        ],
+        &[signer_seeds],
+    )?;
+
+    vault.total_deposited = vault
+        .total_deposited
+        .checked_add(amount)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+pub fn redeem_shares(program_id: &Pubkey, accounts: &[AccountInfo], shares: u64) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let share_mint = next_account_info(accounts_iter)?;
+    let share_account = next_account_info(accounts_iter)?;
+    let holder = next_account_info(accounts_iter)?;
+    let recipient = next_account_info(accounts_iter)?;
+    let token_program = next_account_info(accounts_iter)?;
+
+    if *token_program.key != spl_token::id() {
+        return Err(VaultError::InvalidTokenProgram.into());
+    }
+    if !holder.is_signer {
+        return Err(VaultError::MissingSigner.into());
+    }
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    // Like RedeemDeposit, not bound by the authority's withdrawal lock
+    check_withdrawals_open(&vault)?;
+    if !vault.has_shares() {
+        return Err(VaultError::SharesNotEnabled.into());
+    }
+
+    let assets = vault.convert_to_assets(shares, share_supply(&vault, share_mint)?)?;
+    if assets == 0 {
+        return Err(VaultError::WithdrawalTooSmall.into());
+    }
+    if assets > available_lamports(deposit_account)? {
+        return Err(VaultError::InsufficientVaultFunds.into());
+    }
+
+    invoke(
+        &spl_token::instruction::burn(
+            token_program.key,
+            share_account.key,
+            share_mint.key,
+            holder.key,
+            &[],
+            shares,
+        )?,
+        &[
+            share_account.clone(),
+            share_mint.clone(),
+            holder.clone(),
+            token_program.clone(),
+        ],
+    )?;
+
+    pay_out(&mut vault, deposit_account, recipient, assets)
+}
+
+// Validate the token program (SPL Token or Token-2022) and the vault's associated
+// token account for a mint
+fn check_vault_token_account(
+    deposit_account: &AccountInfo,
+    mint: &AccountInfo,
+    vault_token_account: &AccountInfo,
+    token_program: &AccountInfo,
+) -> ProgramResult {
+    if *token_program.key != spl_token::id() && *token_program.key != spl_token_2022::id() {
+        return Err(VaultError::InvalidTokenProgram.into());
+    }
+    if mint.owner != token_program.key {
+        return Err(VaultError::InvalidTokenProgram.into());
+    }
+    let expected = get_associated_token_address_with_program_id(
+        deposit_account.key,
+        mint.key,
+        token_program.key,
+    );
+    if expected != *vault_token_account.key {
+        return Err(VaultError::InvalidVaultTokenAccount.into());
+    }
+    Ok(())
+}
+
+// Token amount held by an account of either token program
+fn token_account_amount(token_account: &AccountInfo) -> Result<u64, ProgramError> {
+    let data = token_account.try_borrow_data()?;
+    Ok(StateWithExtensions::<TokenAccount>::unpack(&data)?
+        .base
+        .amount)
+}
+
+fn token_account_owner(token_account: &AccountInfo) -> Result<Pubkey, ProgramError> {
+    let data = token_account.try_borrow_data()?;
+    Ok(StateWithExtensions::<TokenAccount>::unpack(&data)?
+        .base
+        .owner)
+}
+
+fn mint_decimals(mint: &AccountInfo) -> Result<u8, ProgramError> {
+    let data = mint.try_borrow_data()?;
+    Ok(StateWithExtensions::<Mint>::unpack(&data)?.base.decimals)
+}
+
+// Load a per-mint record and make sure it belongs to this vault and mint
+fn load_vault_token_state(
+    program_id: &Pubkey,
+    deposit_account: &AccountInfo,
+    mint: &AccountInfo,
+    vault_token_state: &AccountInfo,
+) -> Result<VaultTokenState, ProgramError> {
+    let token_state = VaultTokenState::load_account(vault_token_state, program_id)?;
+    if token_state.vault != *deposit_account.key || token_state.mint != *mint.key {
+        return Err(VaultError::InvalidVaultTokenState.into());
+    }
+    let expected = Pubkey::create_program_address(
+        &[
+            VAULT_TOKEN_SEED,
+            deposit_account.key.as_ref(),
+            mint.key.as_ref(),
+            &[token_state.bump],
+        ],
+        program_id,
+    )
+    .map_err(|_| VaultError::InvalidVaultTokenState)?;
+    if expected != *vault_token_state.key {
+        return Err(VaultError::InvalidVaultTokenState.into());
+    }
+    Ok(token_state)
+}
+
+pub fn deposit_token(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let depositor = next_account_info(accounts_iter)?;
+    let deposit_account = next_account_info(accounts_iter)?;
+    let vault_token_state = next_account_info(accounts_iter)?;
+    let mint = next_account_info(accounts_iter)?;
+    let depositor_token_account = next_account_info(accounts_iter)?;
+    let vault_token_account = next_account_info(accounts_iter)?;
+    let token_program = next_account_info(accounts_iter)?;
+    let associated_token_program = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+    // Anything left over is passed through to transfer-hook programs
+    let hook_accounts = accounts_iter.as_slice();
+
+    check_funding_accounts(depositor, deposit_account, system_program)?;
+    check_vault_token_account(deposit_account, mint, vault_token_account, token_program)?;
+    if *associated_token_program.key != spl_associated_token_account::id() {
+        return Err(VaultError::InvalidAssociatedTokenProgram.into());
+    }
+
+    let mut vault = VaultState::load(deposit_account, program_id)?;
+    check_vault_address(program_id, deposit_account, &vault)?;
+    check_deposits_open(&vault)?;
+    check_not_vesting(&vault)?;
+    // WithdrawToken is the only way tokens leave, and it is closed to
+    // multisig vaults and vaults with a withdrawal delay
+    check_single_authority(&vault)?;
+    check_immediate_withdrawal(&vault)?;
+
+    // The first deposit of a mint creates its record and the vault's token account
+    let mut token_state = if VaultTokenState::is_initialized(vault_token_state)? {
+        load_vault_token_state(program_id, deposit_account, mint, vault_token_state)?
+    } else {
+        let (expected, bump) = find_vault_token_address(deposit_account.key, mint.key, program_id);
+        if expected != *vault_token_state.key {
+            return Err(VaultError::InvalidVaultTokenState.into());
+        }
+        if !vault_token_state.data_is_empty() {
+            return Err(VaultError::InvalidAccountType.into());
+        }
+
+        create_pda_account(
+            depositor,
+            vault_token_state,
+            system_program,
+            program_id,
+            VaultTokenState::LEN,
+            &[
+                VAULT_TOKEN_SEED,
+                deposit_account.key.as_ref(),
+                mint.key.as_ref(),
+                &[bump],
+            ],
+        )?;
+        invoke(
+            &create_associated_token_account_idempotent(
+                depositor.key,
+                deposit_account.key,
+                mint.key,
+                token_program.key,
+            ),
+            &[
+                depositor.clone(),
+                vault_token_account.clone(),
+                deposit_account.clone(),
+                mint.clone(),
+                system_program.clone(),
+                token_program.clone(),
+                associated_token_program.clone(),
+            ],
+        )?;
+
+        VaultTokenState::new(*deposit_account.key, *mint.key, bump)
+    };
+
+    // Transfer the deposit amount
+    let balance_before = token_account_amount(vault_token_account)?;
+    invoke_transfer_checked(
+        token_program.key,
+        depositor_token_account.clone(),
+        mint.clone(),
+        vault_token_account.clone(),
+        depositor.clone(),
+        hook_accounts,
+        amount,
+        mint_decimals(mint)?,
+        &[],
+    )?;
+
+    // Record what actually landed, which is less than `amount` when the mint
+    // withholds a transfer fee
+    let received = token_account_amount(vault_token_account)?
+        .checked_sub(balance_before)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    let was_empty = token_state.balance()? == 0;
+    token_state.total_deposited = token_state
+        .total_deposited
+        .checked_add(received)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    token_state.store(vault_token_state)?;
+
+    if was_empty && received > 0 {
+        vault.token_balances = vault
+            .token_balances
+            .checked_add(1)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+    }
+
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+pub fn withdraw_token(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let deposit_account = next_account_info(accounts_iter)?;
+    let vault_token_state = next_account_info(accounts_iter)?;
+    let mint = next_account_info(accounts_iter)?;
+    let vault_token_account = next_account_info(accounts_iter)?;
+    let recipient_token_account = next_account_info(accounts_iter)?;
+    let authority = next_account_info(accounts_iter)?;
+    let token_program = next_account_info(accounts_iter)?;
+
+    let mut vault = load_authorized_vault(program_id, deposit_account, authority)?;
+    check_withdrawals_open(&vault)?;
+    check_single_authority(&vault)?;
+    check_not_vesting(&vault)?;
+    check_immediate_withdrawal(&vault)?;
+    // Token withdrawals are allowlisted by the owner of the receiving account
+    check_recipient_allowed(
+        program_id,
+        deposit_account,
+        &vault,
+        accounts_iter,
+        &token_account_owner(recipient_token_account)?,
+    )?;
+    check_withdrawal_timing(&mut vault)?;
+    // Anything left over is passed through to transfer-hook programs
+    let hook_accounts = accounts_iter.as_slice();
+    let mut token_state =
+        load_vault_token_state(program_id, deposit_account, mint, vault_token_state)?;
+    check_vault_token_account(deposit_account, mint, vault_token_account, token_program)?;
+
+    // Tokens follow the same withdrawal policy as lamports. Once the policy
+    // share rounds down to nothing the remainder is swept, so the last of a
+    // mint can always leave and the vault can close.
+    let balance = token_state.balance()?;
+    let mut withdrawal_amount = apply_bps(balance, vault.withdrawal_bps)?;
+    if withdrawal_amount == 0 {
+        withdrawal_amount = balance;
+    }
+    if withdrawal_amount == 0 {
+        return Err(VaultError::WithdrawalTooSmall.into());
+    }
+
+    let held = token_account_amount(vault_token_account)?;
+    let withdrawal_amount = std::cmp::min(withdrawal_amount, held);
+    if withdrawal_amount == 0 {
+        return Err(VaultError::InsufficientVaultFunds.into());
+    }
+    token_state.record_outflow(&vault, withdrawal_amount, Clock::get()?.unix_timestamp)?;
+
+    // The vault PDA owns its token account, so it signs the transfer
+    let bump = [vault.bump];
+    let signer_seeds: &[&[u8]] = &[VAULT_SEED, vault.owner.as_ref(), &bump];
+    invoke_transfer_checked(
+        token_program.key,
+        vault_token_account.clone(),
+        mint.clone(),
+        recipient_token_account.clone(),
+        deposit_account.clone(),
+        hook_accounts,
+        withdrawal_amount,
+        mint_decimals(mint)?,
+        &[signer_seeds],
+    )?;
+
+    token_state.total_withdrawn = token_state
+        .total_withdrawn
+        .checked_add(withdrawal_amount)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    token_state.store(vault_token_state)?;
+
+    if token_state.balance()? == 0 {
+        vault.token_balances = vault
+            .token_balances
+            .checked_sub(1)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+    }
+
+    vault.last_activity_at = Clock::get()?.unix_timestamp;
+    vault.store(deposit_account)
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub enum TransferInstruction {
+    DepositInstruction(u64),
+    WithdrawalInstruction,
+    WithdrawAmount(u64),
+    CloseVault,
+    UpdatePolicy(u16),
+    InitializeVault {
+        authority: Pubkey,
+        withdrawal_bps: u16,
+    },
+    DepositToken(u64),
+    WithdrawToken,
+    RedeemDeposit,
+    EnableShares,
+    DepositForShares(u64),
+    RedeemShares(u64),
+    SetWithdrawalLock {
+        withdrawal_interval: i64,
+        unlock_at: i64,
+    },
+    SetRateLimit {
+        window: i64,
+        max_lamports: u64,
+        max_bps: u16,
+    },
+    SetWithdrawalDelay(i64),
+    RequestWithdrawal {
+        amount: u64,
+        recipient: Pubkey,
+    },
+    ExecuteWithdrawal,
+    CancelWithdrawal,
+    SetGuardian(Pubkey),
+    SetPause {
+        deposits_paused: bool,
+        withdrawals_paused: bool,
+    },
+    CreateMultisig {
+        threshold: u8,
+        signers: Vec<Pubkey>,
+    },
+    ProposeWithdrawal {
+        amount: u64,
+        recipient: Pubkey,
+    },
+    Approve,
+    ExecuteProposal,
+    CancelProposal,
+    AddRecipient(Pubkey),
+    RemoveRecipient(Pubkey),
+    ProposeAuthority(Pubkey),
+    AcceptAuthority,
+    CancelAuthorityTransfer,
+    CreateVesting {
+        beneficiary: Pubkey,
+        amount: u64,
+        start: i64,
+        cliff: i64,
+        end: i64,
+        interval: i64,
+        revocable: bool,
+    },
+    Claim,
+    RevokeVesting,
+    CreateStream {
+        recipient: Pubkey,
+        rate: u64,
+        start: i64,
+        stop: i64,
+    },
+    SettleStream,
+    CancelStream,
+    CreateEscrow {
+        payee: Pubkey,
+        arbiter: Pubkey,
+        seed: u64,
+        amount: u64,
+        expires_at: i64,
+    },
+    ReleaseEscrow,
+    RefundEscrow,
+    DisputeEscrow,
+    ResolveEscrow {
+        release: bool,
+    },
+    CloseEscrow,
+}
+
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    input: &[u8],
+) -> ProgramResult {
+    let instruction =
+        TransferInstruction::try_from_slice(input).map_err(|_| VaultError::InvalidInstruction)?;
+    let result = match instruction {
+        TransferInstruction::DepositInstruction(amount) => deposit(program_id, accounts, amount),
+        TransferInstruction::WithdrawalInstruction => withdraw(program_id, accounts),
+        TransferInstruction::WithdrawAmount(amount) => {
+            withdraw_amount(program_id, accounts, amount)
+        }
+        TransferInstruction::CloseVault => close_vault(program_id, accounts),
+        TransferInstruction::UpdatePolicy(withdrawal_bps) => {
+            update_policy(program_id, accounts, withdrawal_bps)
+        }
+        TransferInstruction::InitializeVault {
+            authority,
+            withdrawal_bps,
+        } => initialize_vault(program_id, accounts, authority, withdrawal_bps),
+        TransferInstruction::DepositToken(amount) => deposit_token(program_id, accounts, amount),
+        TransferInstruction::WithdrawToken => withdraw_token(program_id, accounts),
+        TransferInstruction::RedeemDeposit => redeem_deposit(program_id, accounts),
+        TransferInstruction::EnableShares => enable_shares(program_id, accounts),
+        TransferInstruction::DepositForShares(amount) => {
+            deposit_for_shares(program_id, accounts, amount)
+        }
+        TransferInstruction::RedeemShares(shares) => redeem_shares(program_id, accounts, shares),
+        TransferInstruction::SetWithdrawalLock {
+            withdrawal_interval,
+            unlock_at,
+        } => set_withdrawal_lock(program_id, accounts, withdrawal_interval, unlock_at),
+        TransferInstruction::SetRateLimit {
+            window,
+            max_lamports,
+            max_bps,
+        } => set_rate_limit(program_id, accounts, window, max_lamports, max_bps),
+        TransferInstruction::SetWithdrawalDelay(withdrawal_delay) => {
+            set_withdrawal_delay(program_id, accounts, withdrawal_delay)
+        }
+        TransferInstruction::RequestWithdrawal { amount, recipient } => {
+            request_withdrawal(program_id, accounts, amount, recipient)
+        }
+        TransferInstruction::ExecuteWithdrawal => execute_withdrawal(program_id, accounts),
+        TransferInstruction::CancelWithdrawal => cancel_withdrawal(program_id, accounts),
+        TransferInstruction::SetGuardian(guardian) => set_guardian(program_id, accounts, guardian),
+        TransferInstruction::SetPause {
+            deposits_paused,
+            withdrawals_paused,
+        } => set_pause(program_id, accounts, deposits_paused, withdrawals_paused),
+        TransferInstruction::CreateMultisig { threshold, signers } => {
+            create_multisig(program_id, accounts, threshold, signers)
+        }
+        TransferInstruction::ProposeWithdrawal { amount, recipient } => {
+            propose_withdrawal(program_id, accounts, amount, recipient)
+        }
+        TransferInstruction::Approve => approve(program_id, accounts),
+        TransferInstruction::ExecuteProposal => execute_proposal(program_id, accounts),
+        TransferInstruction::CancelProposal => cancel_proposal(program_id, accounts),
+        TransferInstruction::AddRecipient(recipient) => {
+            add_recipient(program_id, accounts, recipient)
+        }
+        TransferInstruction::RemoveRecipient(recipient) => {
+            remove_recipient(program_id, accounts, recipient)
+        }
+        TransferInstruction::ProposeAuthority(new_authority) => {
+            propose_authority(program_id, accounts, new_authority)
+        }
+        TransferInstruction::AcceptAuthority => accept_authority(program_id, accounts),
+        TransferInstruction::CancelAuthorityTransfer => {
+            cancel_authority_transfer(program_id, accounts)
+        }
+        TransferInstruction::CreateVesting {
+            beneficiary,
+            amount,
+            start,
+            cliff,
+            end,
+            interval,
+            revocable,
+        } => create_vesting(
+            program_id,
+            accounts,
+            beneficiary,
+            amount,
+            start,
+            cliff,
+            end,
+            interval,
+            revocable,
+        ),
+        TransferInstruction::Claim => claim(program_id, accounts),
+        TransferInstruction::RevokeVesting => revoke_vesting(program_id, accounts),
+        TransferInstruction::CreateStream {
+            recipient,
+            rate,
+            start,
+            stop,
+        } => create_stream(program_id, accounts, recipient, rate, start, stop),
+        TransferInstruction::SettleStream => settle_stream(program_id, accounts),
+        TransferInstruction::CancelStream => cancel_stream(program_id, accounts),
+        TransferInstruction::CreateEscrow {
+            payee,
+            arbiter,
+            seed,
+            amount,
+            expires_at,
+        } => create_escrow(
+            program_id, accounts, payee, arbiter, seed, amount, expires_at,
+        ),
+        TransferInstruction::ReleaseEscrow => release_escrow(program_id, accounts),
+        TransferInstruction::RefundEscrow => refund_escrow(program_id, accounts),
+        TransferInstruction::DisputeEscrow => dispute_escrow(program_id, accounts),
+        TransferInstruction::ResolveEscrow { release } => {
+            resolve_escrow(program_id, accounts, release)
+        }
+        TransferInstruction::CloseEscrow => close_escrow(program_id, accounts),
+    };
+
+    // Log custom errors by name so failures are readable in transaction logs
+    if let Err(error) = &result {
+        error.print::<VaultError>();
     }
+    result
 }
@@ -0,0 +1,1104 @@
+use crate::error::VaultError;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+pub const VAULT_SEED: &[u8] = b"vault";
+pub const VAULT_DISCRIMINATOR: [u8; 8] = *b"VAULT\0\0\0";
+pub const VAULT_VERSION: u8 = 1;
+const VAULT_RESERVED: usize = 190;
+
+pub const VAULT_TOKEN_SEED: &[u8] = b"vault_token";
+pub const VAULT_TOKEN_DISCRIMINATOR: [u8; 8] = *b"VTOKEN\0\0";
+const VAULT_TOKEN_RESERVED: usize = 16;
+
+// Share mints live at a PDA derived from ["shares", vault], with the vault as
+// mint authority. Shares use the same precision as lamports.
+pub const SHARE_MINT_SEED: &[u8] = b"shares";
+pub const SHARE_DECIMALS: u8 = 9;
+
+pub const WITHDRAWAL_SEED: &[u8] = b"withdrawal";
+pub const WITHDRAWAL_DISCRIMINATOR: [u8; 8] = *b"PENDWDRL";
+const WITHDRAWAL_RESERVED: usize = 16;
+
+// Multisig configs live at a PDA derived from ["multisig", vault] and
+// withdrawal proposals at ["proposal", multisig, id]
+pub const MULTISIG_SEED: &[u8] = b"multisig";
+pub const MULTISIG_DISCRIMINATOR: [u8; 8] = *b"MULTISIG";
+pub const MAX_MULTISIG_SIGNERS: usize = 11;
+const MULTISIG_RESERVED: usize = 28;
+
+pub const PROPOSAL_SEED: &[u8] = b"proposal";
+pub const PROPOSAL_DISCRIMINATOR: [u8; 8] = *b"PROPOSAL";
+const PROPOSAL_RESERVED: usize = 16;
+
+// Recipient allowlists live at a PDA derived from ["allowlist", vault]
+pub const ALLOWLIST_SEED: &[u8] = b"allowlist";
+pub const ALLOWLIST_DISCRIMINATOR: [u8; 8] = *b"ALLOWLST";
+pub const MAX_ALLOWED_RECIPIENTS: usize = 16;
+const ALLOWLIST_RESERVED: usize = 32;
+
+pub const VESTING_SEED: &[u8] = b"vesting";
+pub const VESTING_DISCRIMINATOR: [u8; 8] = *b"VESTING\0";
+const VESTING_RESERVED: usize = 32;
+
+pub const STREAM_SEED: &[u8] = b"stream";
+pub const STREAM_DISCRIMINATOR: [u8; 8] = *b"STREAM\0\0";
+const STREAM_RESERVED: usize = 32;
+
+pub const ESCROW_SEED: &[u8] = b"escrow";
+pub const ESCROW_DISCRIMINATOR: [u8; 8] = *b"ESCROW\0\0";
+const ESCROW_RESERVED: usize = 24;
+// A dispute the arbiter leaves unresolved this long can be refunded to the payer
+pub const ESCROW_DISPUTE_TIMEOUT: i64 = 30 * 24 * 60 * 60;
+
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+pub const RECEIPT_DISCRIMINATOR: [u8; 8] = *b"RECEIPT\0";
+const RECEIPT_RESERVED: usize = 32;
+
+// Withdrawal policy, in basis points of the vault balance paid per withdrawal
+pub const MAX_BPS: u16 = 10_000;
+pub const DEFAULT_WITHDRAWAL_BPS: u16 = 1_000;
+
+// amount * numerator / denominator, rounding down
+pub fn mul_div(amount: u64, numerator: u128, denominator: u128) -> Result<u64, ProgramError> {
+    let result = (amount as u128)
+        .checked_mul(numerator)
+        .ok_or(VaultError::ArithmeticOverflow)?
+        / denominator;
+    u64::try_from(result).map_err(|_| VaultError::ArithmeticOverflow.into())
+}
+
+// Apply a basis-point rate to an amount, rounding down
+pub fn apply_bps(amount: u64, bps: u16) -> Result<u64, ProgramError> {
+    mul_div(amount, bps as u128, MAX_BPS as u128)
+}
+
+pub fn validate_withdrawal_bps(withdrawal_bps: u16) -> ProgramResult {
+    if withdrawal_bps == 0 || withdrawal_bps > MAX_BPS {
+        return Err(VaultError::InvalidPolicy.into());
+    }
+    Ok(())
+}
+
+// Every account the program owns starts with an 8-byte discriminator naming its type
+pub trait ProgramAccount: BorshSerialize + BorshDeserialize {
+    const DISCRIMINATOR: [u8; 8];
+    const LEN: usize;
+
+    fn is_initialized(account: &AccountInfo) -> Result<bool, ProgramError> {
+        let data = account.try_borrow_data()?;
+        Ok(data.len() >= 8 && data[..8] == Self::DISCRIMINATOR)
+    }
+
+    // Deserialize, telling apart uninitialized accounts and accounts of another type
+    fn load_account(account: &AccountInfo, program_id: &Pubkey) -> Result<Self, ProgramError> {
+        if account.owner != program_id {
+            return Err(VaultError::InvalidVaultOwner.into());
+        }
+        let data = account.try_borrow_data()?;
+        if data.len() < 8 || data[..8] == [0; 8] {
+            return Err(VaultError::UninitializedVault.into());
+        }
+        if data[..8] != Self::DISCRIMINATOR || data.len() != Self::LEN {
+            return Err(VaultError::InvalidAccountType.into());
+        }
+        Self::deserialize(&mut &data[..]).map_err(|_| VaultError::InvalidAccountType.into())
+    }
+
+    fn store(&self, account: &AccountInfo) -> ProgramResult {
+        let mut data = account.try_borrow_mut_data()?;
+        self.serialize(&mut &mut data[..])?;
+        Ok(())
+    }
+}
+
+// Vaults live at a PDA derived from ["vault", owner]
+pub fn find_vault_address(owner: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_SEED, owner.as_ref()], program_id)
+}
+
+// Per-mint token records live at a PDA derived from ["vault_token", vault, mint]
+pub fn find_vault_token_address(
+    vault: &Pubkey,
+    mint: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[VAULT_TOKEN_SEED, vault.as_ref(), mint.as_ref()],
+        program_id,
+    )
+}
+
+pub fn find_share_mint_address(vault: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SHARE_MINT_SEED, vault.as_ref()], program_id)
+}
+
+// Pending withdrawals live at a PDA derived from ["withdrawal", vault, id]
+pub fn find_pending_withdrawal_address(
+    vault: &Pubkey,
+    id: u64,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[WITHDRAWAL_SEED, vault.as_ref(), &id.to_le_bytes()],
+        program_id,
+    )
+}
+
+pub fn find_multisig_address(vault: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MULTISIG_SEED, vault.as_ref()], program_id)
+}
+
+pub fn find_proposal_address(multisig: &Pubkey, id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PROPOSAL_SEED, multisig.as_ref(), &id.to_le_bytes()],
+        program_id,
+    )
+}
+
+pub fn find_allowlist_address(vault: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ALLOWLIST_SEED, vault.as_ref()], program_id)
+}
+
+// A vault's vesting schedule lives at a PDA derived from ["vesting", vault]
+pub fn find_vesting_address(vault: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VESTING_SEED, vault.as_ref()], program_id)
+}
+
+// Payout streams live at a PDA derived from ["stream", vault, recipient]
+pub fn find_stream_address(
+    vault: &Pubkey,
+    recipient: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[STREAM_SEED, vault.as_ref(), recipient.as_ref()],
+        program_id,
+    )
+}
+
+// Escrows live at a PDA derived from ["escrow", payer, payee, seed], so a
+// payer can hold several escrows with the same payee
+pub fn find_escrow_address(
+    payer: &Pubkey,
+    payee: &Pubkey,
+    seed: u64,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            ESCROW_SEED,
+            payer.as_ref(),
+            payee.as_ref(),
+            &seed.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+// Receipts live at a PDA derived from ["receipt", vault, depositor]
+pub fn find_receipt_address(
+    vault: &Pubkey,
+    depositor: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[RECEIPT_SEED, vault.as_ref(), depositor.as_ref()],
+        program_id,
+    )
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct VaultState {
+    pub discriminator: [u8; 8],
+    pub version: u8,
+    // Creator of the vault; the vault address is derived from it
+    pub owner: Pubkey,
+    // Key allowed to withdraw and manage the vault
+    pub authority: Pubkey,
+    pub bump: u8,
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    pub created_at: i64,
+    pub last_activity_at: i64,
+    pub withdrawal_bps: u16,
+    // Number of mints whose VaultTokenState records a non-zero balance
+    pub token_balances: u32,
+    // Sum of the principal still owed to depositors through their receipts
+    pub outstanding_principal: u64,
+    // Mint of the vault's share token; the default pubkey when shares are off
+    pub share_mint: Pubkey,
+    // Minimum seconds between authority withdrawals; 0 disables the cooldown
+    pub withdrawal_interval: i64,
+    // Unix timestamp before which nothing can leave the vault; 0 when unlocked
+    pub unlock_at: i64,
+    pub last_withdrawal_at: i64,
+    // Cap on authority outflows per window of `rate_limit_window` seconds, as an
+    // absolute lamport amount and/or basis points of the balance at window start.
+    // A window of 0 disables the limit; a cap of 0 is not enforced.
+    pub rate_limit_window: i64,
+    pub rate_limit_lamports: u64,
+    pub rate_limit_bps: u16,
+    pub window_started_at: i64,
+    pub window_outflow: u64,
+    // Seconds a requested withdrawal waits before it can execute; when non-zero,
+    // immediate withdrawals are refused
+    pub withdrawal_delay: i64,
+    pub next_withdrawal_id: u64,
+    pub pending_withdrawals: u32,
+    // Optional key that can pause the vault and cancel pending withdrawals but
+    // never move funds; the default pubkey when there is no guardian
+    pub guardian: Pubkey,
+    pub deposits_paused: bool,
+    pub withdrawals_paused: bool,
+    // Multisig config governing withdrawals; the default pubkey when the
+    // authority withdraws on its own
+    pub multisig: Pubkey,
+    // Allowlist of withdrawal recipients; the default pubkey when any recipient
+    // may be paid
+    pub allowlist: Pubkey,
+    // Key proposed to take over as authority; the default pubkey when no
+    // transfer is pending
+    pub pending_authority: Pubkey,
+    // Vesting schedule that replaces the withdrawal policy; the default pubkey
+    // for ordinary vaults
+    pub vesting: Pubkey,
+    // Streams not yet fully settled; the vault cannot close over them
+    pub active_streams: u32,
+    pub reserved: [u8; VAULT_RESERVED],
+}
+
+impl ProgramAccount for VaultState {
+    const DISCRIMINATOR: [u8; 8] = VAULT_DISCRIMINATOR;
+    const LEN: usize = 8
+        + 1
+        + 32
+        + 32
+        + 1
+        + 8
+        + 8
+        + 8
+        + 8
+        + 2
+        + 4
+        + 8
+        + 32
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 2
+        + 8
+        + 8
+        + 8
+        + 8
+        + 4
+        + 32
+        + 1
+        + 1
+        + 32
+        + 32
+        + 32
+        + 32
+        + 4
+        + VAULT_RESERVED;
+}
+
+impl VaultState {
+    pub fn new(owner: Pubkey, authority: Pubkey, bump: u8, withdrawal_bps: u16, now: i64) -> Self {
+        Self {
+            discriminator: VAULT_DISCRIMINATOR,
+            version: VAULT_VERSION,
+            owner,
+            authority,
+            bump,
+            total_deposited: 0,
+            total_withdrawn: 0,
+            created_at: now,
+            last_activity_at: now,
+            withdrawal_bps,
+            token_balances: 0,
+            outstanding_principal: 0,
+            share_mint: Pubkey::default(),
+            withdrawal_interval: 0,
+            unlock_at: 0,
+            last_withdrawal_at: 0,
+            rate_limit_window: 0,
+            rate_limit_lamports: 0,
+            rate_limit_bps: 0,
+            window_started_at: 0,
+            window_outflow: 0,
+            withdrawal_delay: 0,
+            next_withdrawal_id: 0,
+            pending_withdrawals: 0,
+            guardian: Pubkey::default(),
+            deposits_paused: false,
+            withdrawals_paused: false,
+            multisig: Pubkey::default(),
+            allowlist: Pubkey::default(),
+            pending_authority: Pubkey::default(),
+            vesting: Pubkey::default(),
+            active_streams: 0,
+            reserved: [0; VAULT_RESERVED],
+        }
+    }
+
+    // Lamports still attributed to depositors
+    pub fn balance(&self) -> Result<u64, ProgramError> {
+        self.total_deposited
+            .checked_sub(self.total_withdrawn)
+            .ok_or_else(|| VaultError::ArithmeticOverflow.into())
+    }
+
+    // Lamports the authority may move; principal owed to depositors through
+    // their receipts is only paid out by RedeemDeposit, and on share vaults
+    // every lamport backs the shares
+    pub fn authority_balance(&self) -> Result<u64, ProgramError> {
+        if self.has_shares() {
+            return Err(VaultError::SharesEnabled.into());
+        }
+        Ok(self.balance()?.saturating_sub(self.outstanding_principal))
+    }
+
+    // Amount a single policy-based withdrawal pays out
+    pub fn policy_amount(&self) -> Result<u64, ProgramError> {
+        apply_bps(self.authority_balance()?, self.withdrawal_bps)
+    }
+
+    pub fn next_withdrawal_at(&self) -> i64 {
+        self.last_withdrawal_at
+            .saturating_add(self.withdrawal_interval)
+    }
+
+    fn window_elapsed(&self, now: i64) -> bool {
+        now >= self
+            .window_started_at
+            .saturating_add(self.rate_limit_window)
+    }
+
+    // Lamports that may still leave in the current rate-limit window
+    pub fn outflow_headroom(&self, now: i64) -> Result<u64, ProgramError> {
+        if self.rate_limit_window == 0 {
+            return Ok(u64::MAX);
+        }
+        let window_outflow = if self.window_elapsed(now) {
+            0
+        } else {
+            self.window_outflow
+        };
+
+        let mut headroom = u64::MAX;
+        if self.rate_limit_lamports > 0 {
+            headroom = self.rate_limit_lamports.saturating_sub(window_outflow);
+        }
+        if self.rate_limit_bps > 0 {
+            let window_balance = self
+                .balance()?
+                .checked_add(window_outflow)
+                .ok_or(VaultError::ArithmeticOverflow)?;
+            let limit = apply_bps(window_balance, self.rate_limit_bps)?;
+            headroom = std::cmp::min(headroom, limit.saturating_sub(window_outflow));
+        }
+        Ok(headroom)
+    }
+
+    // Count an outflow against the current rate-limit window, starting a new
+    // window once the previous one has elapsed
+    pub fn record_outflow(&mut self, amount: u64, now: i64) -> ProgramResult {
+        if self.rate_limit_window == 0 {
+            return Ok(());
+        }
+        if amount > self.outflow_headroom(now)? {
+            return Err(VaultError::RateLimitExceeded.into());
+        }
+        if self.window_elapsed(now) {
+            self.window_started_at = now;
+            self.window_outflow = 0;
+        }
+
+        self.window_outflow = self
+            .window_outflow
+            .checked_add(amount)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    pub fn has_guardian(&self) -> bool {
+        self.guardian != Pubkey::default()
+    }
+
+    pub fn has_multisig(&self) -> bool {
+        self.multisig != Pubkey::default()
+    }
+
+    pub fn has_allowlist(&self) -> bool {
+        self.allowlist != Pubkey::default()
+    }
+
+    pub fn has_vesting(&self) -> bool {
+        self.vesting != Pubkey::default()
+    }
+
+    pub fn has_shares(&self) -> bool {
+        self.share_mint != Pubkey::default()
+    }
+
+    // ERC-4626 style conversions. The virtual share and lamport keep the first
+    // depositor from inflating the share price, and both directions round
+    // down so the vault never pays out more than it holds.
+    pub fn convert_to_shares(&self, assets: u64, share_supply: u64) -> Result<u64, ProgramError> {
+        mul_div(
+            assets,
+            share_supply as u128 + 1,
+            self.balance()? as u128 + 1,
+        )
+    }
+
+    pub fn convert_to_assets(&self, shares: u64, share_supply: u64) -> Result<u64, ProgramError> {
+        mul_div(
+            shares,
+            self.balance()? as u128 + 1,
+            share_supply as u128 + 1,
+        )
+    }
+
+    // Deserialize a vault, rejecting accounts that are not program-owned vaults
+    pub fn load(account: &AccountInfo, program_id: &Pubkey) -> Result<Self, ProgramError> {
+        let state = Self::load_account(account, program_id)?;
+        if state.version != VAULT_VERSION {
+            return Err(VaultError::UnsupportedVersion.into());
+        }
+        Ok(state)
+    }
+}
+
+// Totals for one SPL mint held in the vault's associated token account
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct VaultTokenState {
+    pub discriminator: [u8; 8],
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub bump: u8,
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    // Outflows of this mint in the vault's current rate-limit window
+    pub window_started_at: i64,
+    pub window_outflow: u64,
+    pub reserved: [u8; VAULT_TOKEN_RESERVED],
+}
+
+impl ProgramAccount for VaultTokenState {
+    const DISCRIMINATOR: [u8; 8] = VAULT_TOKEN_DISCRIMINATOR;
+    const LEN: usize = 8 + 32 + 32 + 1 + 8 + 8 + 8 + 8 + VAULT_TOKEN_RESERVED;
+}
+
+impl VaultTokenState {
+    pub fn new(vault: Pubkey, mint: Pubkey, bump: u8) -> Self {
+        Self {
+            discriminator: VAULT_TOKEN_DISCRIMINATOR,
+            vault,
+            mint,
+            bump,
+            total_deposited: 0,
+            total_withdrawn: 0,
+            window_started_at: 0,
+            window_outflow: 0,
+            reserved: [0; VAULT_TOKEN_RESERVED],
+        }
+    }
+
+    // Tokens still attributed to depositors
+    pub fn balance(&self) -> Result<u64, ProgramError> {
+        self.total_deposited
+            .checked_sub(self.total_withdrawn)
+            .ok_or_else(|| VaultError::ArithmeticOverflow.into())
+    }
+
+    // Count a token outflow against the vault's rate limit. Each mint has its
+    // own window under the vault's window length and basis-point cap; the
+    // lamport cap does not apply to tokens.
+    pub fn record_outflow(&mut self, vault: &VaultState, amount: u64, now: i64) -> ProgramResult {
+        if vault.rate_limit_window == 0 {
+            return Ok(());
+        }
+        if now
+            >= self
+                .window_started_at
+                .saturating_add(vault.rate_limit_window)
+        {
+            self.window_started_at = now;
+            self.window_outflow = 0;
+        }
+
+        let outflow = self
+            .window_outflow
+            .checked_add(amount)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        if vault.rate_limit_bps > 0 {
+            let window_balance = self
+                .balance()?
+                .checked_add(self.window_outflow)
+                .ok_or(VaultError::ArithmeticOverflow)?;
+            if outflow > apply_bps(window_balance, vault.rate_limit_bps)? {
+                return Err(VaultError::RateLimitExceeded.into());
+            }
+        }
+
+        self.window_outflow = outflow;
+        Ok(())
+    }
+}
+
+// One depositor's contributions to a vault
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct DepositReceipt {
+    pub discriminator: [u8; 8],
+    pub vault: Pubkey,
+    pub depositor: Pubkey,
+    pub bump: u8,
+    pub total_deposited: u64,
+    // Lamports actually paid back by redemptions
+    pub total_withdrawn: u64,
+    // Deposits not yet redeemed
+    pub principal: u64,
+    pub reserved: [u8; RECEIPT_RESERVED],
+}
+
+impl ProgramAccount for DepositReceipt {
+    const DISCRIMINATOR: [u8; 8] = RECEIPT_DISCRIMINATOR;
+    const LEN: usize = 8 + 32 + 32 + 1 + 8 + 8 + 8 + RECEIPT_RESERVED;
+}
+
+impl DepositReceipt {
+    pub fn new(vault: Pubkey, depositor: Pubkey, bump: u8) -> Self {
+        Self {
+            discriminator: RECEIPT_DISCRIMINATOR,
+            vault,
+            depositor,
+            bump,
+            total_deposited: 0,
+            total_withdrawn: 0,
+            principal: 0,
+            reserved: [0; RECEIPT_RESERVED],
+        }
+    }
+}
+
+// A withdrawal requested by the authority, payable once `executable_at` passes
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct PendingWithdrawal {
+    pub discriminator: [u8; 8],
+    pub vault: Pubkey,
+    pub id: u64,
+    pub bump: u8,
+    pub amount: u64,
+    pub recipient: Pubkey,
+    // Refunded the account's rent when the withdrawal executes or is cancelled
+    pub rent_payer: Pubkey,
+    pub requested_at: i64,
+    pub executable_at: i64,
+    pub reserved: [u8; WITHDRAWAL_RESERVED],
+}
+
+impl ProgramAccount for PendingWithdrawal {
+    const DISCRIMINATOR: [u8; 8] = WITHDRAWAL_DISCRIMINATOR;
+    const LEN: usize = 8 + 32 + 8 + 1 + 8 + 32 + 32 + 8 + 8 + WITHDRAWAL_RESERVED;
+}
+
+impl PendingWithdrawal {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        vault: Pubkey,
+        id: u64,
+        bump: u8,
+        amount: u64,
+        recipient: Pubkey,
+        rent_payer: Pubkey,
+        requested_at: i64,
+        executable_at: i64,
+    ) -> Self {
+        Self {
+            discriminator: WITHDRAWAL_DISCRIMINATOR,
+            vault,
+            id,
+            bump,
+            amount,
+            recipient,
+            rent_payer,
+            requested_at,
+            executable_at,
+            reserved: [0; WITHDRAWAL_RESERVED],
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct AllowedRecipient {
+    pub recipient: Pubkey,
+    // Newly added recipients can only be paid from this timestamp on
+    pub active_at: i64,
+}
+
+// Bounded set of recipients a vault may pay. Only the first `count` entries
+// are in use.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct RecipientAllowlist {
+    pub discriminator: [u8; 8],
+    pub vault: Pubkey,
+    pub bump: u8,
+    pub count: u8,
+    pub entries: [AllowedRecipient; MAX_ALLOWED_RECIPIENTS],
+    pub reserved: [u8; ALLOWLIST_RESERVED],
+}
+
+impl ProgramAccount for RecipientAllowlist {
+    const DISCRIMINATOR: [u8; 8] = ALLOWLIST_DISCRIMINATOR;
+    const LEN: usize = 8 + 32 + 1 + 1 + (32 + 8) * MAX_ALLOWED_RECIPIENTS + ALLOWLIST_RESERVED;
+}
+
+impl RecipientAllowlist {
+    pub fn new(vault: Pubkey, bump: u8) -> Self {
+        Self {
+            discriminator: ALLOWLIST_DISCRIMINATOR,
+            vault,
+            bump,
+            count: 0,
+            entries: [AllowedRecipient::default(); MAX_ALLOWED_RECIPIENTS],
+            reserved: [0; ALLOWLIST_RESERVED],
+        }
+    }
+
+    fn position(&self, recipient: &Pubkey) -> Option<usize> {
+        self.entries[..self.count as usize]
+            .iter()
+            .position(|entry| entry.recipient == *recipient)
+    }
+
+    pub fn is_allowed(&self, recipient: &Pubkey, now: i64) -> bool {
+        self.position(recipient)
+            .is_some_and(|index| now >= self.entries[index].active_at)
+    }
+
+    pub fn add(&mut self, recipient: Pubkey, active_at: i64) -> ProgramResult {
+        if self.position(&recipient).is_some() {
+            return Err(VaultError::RecipientAlreadyAllowed.into());
+        }
+        let count = self.count as usize;
+        if count == MAX_ALLOWED_RECIPIENTS {
+            return Err(VaultError::AllowlistFull.into());
+        }
+        self.entries[count] = AllowedRecipient {
+            recipient,
+            active_at,
+        };
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, recipient: &Pubkey) -> ProgramResult {
+        let index = self
+            .position(recipient)
+            .ok_or(VaultError::RecipientNotAllowed)?;
+        let last = self.count as usize - 1;
+        self.entries[index] = self.entries[last];
+        self.entries[last] = AllowedRecipient::default();
+        self.count -= 1;
+        Ok(())
+    }
+}
+
+// M-of-N signer set that approves withdrawals from a vault. Only the first
+// `signer_count` entries of `signers` are in use.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct MultisigConfig {
+    pub discriminator: [u8; 8],
+    pub vault: Pubkey,
+    pub bump: u8,
+    pub threshold: u8,
+    pub signer_count: u8,
+    pub signers: [Pubkey; MAX_MULTISIG_SIGNERS],
+    pub next_proposal_id: u64,
+    // Proposals not yet executed or cancelled; the vault cannot close over them
+    pub open_proposals: u32,
+    pub reserved: [u8; MULTISIG_RESERVED],
+}
+
+impl ProgramAccount for MultisigConfig {
+    const DISCRIMINATOR: [u8; 8] = MULTISIG_DISCRIMINATOR;
+    const LEN: usize = 8 + 32 + 1 + 1 + 1 + 32 * MAX_MULTISIG_SIGNERS + 8 + 4 + MULTISIG_RESERVED;
+}
+
+impl MultisigConfig {
+    pub fn new(
+        vault: Pubkey,
+        bump: u8,
+        threshold: u8,
+        signers: &[Pubkey],
+    ) -> Result<Self, ProgramError> {
+        if signers.is_empty()
+            || signers.len() > MAX_MULTISIG_SIGNERS
+            || threshold == 0
+            || threshold as usize > signers.len()
+        {
+            return Err(VaultError::InvalidMultisig.into());
+        }
+        for (i, signer) in signers.iter().enumerate() {
+            if *signer == Pubkey::default() || signers[..i].contains(signer) {
+                return Err(VaultError::InvalidMultisig.into());
+            }
+        }
+
+        let mut stored = [Pubkey::default(); MAX_MULTISIG_SIGNERS];
+        stored[..signers.len()].copy_from_slice(signers);
+        Ok(Self {
+            discriminator: MULTISIG_DISCRIMINATOR,
+            vault,
+            bump,
+            threshold,
+            signer_count: signers.len() as u8,
+            signers: stored,
+            next_proposal_id: 0,
+            open_proposals: 0,
+            reserved: [0; MULTISIG_RESERVED],
+        })
+    }
+
+    // Position of a key in the signer set, used as its bit in proposal approvals
+    pub fn signer_index(&self, key: &Pubkey) -> Option<usize> {
+        self.signers[..self.signer_count as usize]
+            .iter()
+            .position(|signer| signer == key)
+    }
+}
+
+// A withdrawal waiting on multisig approvals. Each approval sets the bit of the
+// approving signer's index.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct WithdrawalProposal {
+    pub discriminator: [u8; 8],
+    pub multisig: Pubkey,
+    pub id: u64,
+    pub bump: u8,
+    pub amount: u64,
+    pub recipient: Pubkey,
+    // Paid the account's rent and gets it back when the proposal closes
+    pub proposer: Pubkey,
+    pub approvals: u16,
+    pub created_at: i64,
+    pub reserved: [u8; PROPOSAL_RESERVED],
+}
+
+impl ProgramAccount for WithdrawalProposal {
+    const DISCRIMINATOR: [u8; 8] = PROPOSAL_DISCRIMINATOR;
+    const LEN: usize = 8 + 32 + 8 + 1 + 8 + 32 + 32 + 2 + 8 + PROPOSAL_RESERVED;
+}
+
+impl WithdrawalProposal {
+    pub fn new(
+        multisig: Pubkey,
+        id: u64,
+        bump: u8,
+        amount: u64,
+        recipient: Pubkey,
+        proposer: Pubkey,
+        created_at: i64,
+    ) -> Self {
+        Self {
+            discriminator: PROPOSAL_DISCRIMINATOR,
+            multisig,
+            id,
+            bump,
+            amount,
+            recipient,
+            proposer,
+            approvals: 0,
+            created_at,
+            reserved: [0; PROPOSAL_RESERVED],
+        }
+    }
+
+    pub fn approval_count(&self) -> u8 {
+        self.approvals.count_ones() as u8
+    }
+}
+
+// Funds vesting linearly from `start` to `end` in steps of `interval` seconds,
+// with nothing claimable before `cliff`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct VestingSchedule {
+    pub discriminator: [u8; 8],
+    pub vault: Pubkey,
+    pub bump: u8,
+    pub beneficiary: Pubkey,
+    pub funder: Pubkey,
+    // Lamports under the schedule; cut down to the vested amount on revoke
+    pub total_amount: u64,
+    pub claimed: u64,
+    pub start: i64,
+    pub cliff: i64,
+    pub end: i64,
+    // 0 vests continuously
+    pub interval: i64,
+    pub revocable: bool,
+    pub revoked: bool,
+    pub reserved: [u8; VESTING_RESERVED],
+}
+
+impl ProgramAccount for VestingSchedule {
+    const DISCRIMINATOR: [u8; 8] = VESTING_DISCRIMINATOR;
+    const LEN: usize = 8 + 32 + 1 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 1 + 1 + VESTING_RESERVED;
+}
+
+impl VestingSchedule {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        vault: Pubkey,
+        bump: u8,
+        beneficiary: Pubkey,
+        funder: Pubkey,
+        total_amount: u64,
+        start: i64,
+        cliff: i64,
+        end: i64,
+        interval: i64,
+        revocable: bool,
+    ) -> Result<Self, ProgramError> {
+        let duration = end
+            .checked_sub(start)
+            .ok_or(VaultError::InvalidVestingSchedule)?;
+        if total_amount == 0
+            || duration <= 0
+            || cliff < start
+            || cliff > end
+            || interval < 0
+            || interval > duration
+        {
+            return Err(VaultError::InvalidVestingSchedule.into());
+        }
+        Ok(Self {
+            discriminator: VESTING_DISCRIMINATOR,
+            vault,
+            bump,
+            beneficiary,
+            funder,
+            total_amount,
+            claimed: 0,
+            start,
+            cliff,
+            end,
+            interval,
+            revocable,
+            revoked: false,
+            reserved: [0; VESTING_RESERVED],
+        })
+    }
+
+    pub fn vested_amount(&self, now: i64) -> Result<u64, ProgramError> {
+        if self.revoked || now >= self.end {
+            return Ok(self.total_amount);
+        }
+        if now < self.cliff {
+            return Ok(0);
+        }
+        let mut elapsed = now
+            .checked_sub(self.start)
+            .ok_or(VaultError::InvalidVestingSchedule)?;
+        if self.interval > 0 {
+            elapsed -= elapsed % self.interval;
+        }
+        let duration = self
+            .end
+            .checked_sub(self.start)
+            .ok_or(VaultError::InvalidVestingSchedule)?;
+        mul_div(self.total_amount, elapsed as u128, duration as u128)
+    }
+
+    pub fn claimable(&self, now: i64) -> Result<u64, ProgramError> {
+        self.vested_amount(now)?
+            .checked_sub(self.claimed)
+            .ok_or_else(|| VaultError::ArithmeticOverflow.into())
+    }
+
+    // Freeze the schedule at what has vested so far and return the unvested rest
+    pub fn revoke(&mut self, now: i64) -> Result<u64, ProgramError> {
+        let vested = self.vested_amount(now)?;
+        let unvested = self
+            .total_amount
+            .checked_sub(vested)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        self.total_amount = vested;
+        self.revoked = true;
+        Ok(unvested)
+    }
+}
+
+// Lamports paid to a recipient at `rate` per second between `start` and
+// `stop`. Everything before `settled_until` has been paid out.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct PayoutStream {
+    pub discriminator: [u8; 8],
+    pub vault: Pubkey,
+    pub bump: u8,
+    pub recipient: Pubkey,
+    // Refunded the account's rent once the stream is fully settled
+    pub rent_payer: Pubkey,
+    pub rate: u64,
+    pub start: i64,
+    pub stop: i64,
+    pub settled_until: i64,
+    pub total_paid: u64,
+    pub reserved: [u8; STREAM_RESERVED],
+}
+
+impl ProgramAccount for PayoutStream {
+    const DISCRIMINATOR: [u8; 8] = STREAM_DISCRIMINATOR;
+    const LEN: usize = 8 + 32 + 1 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + STREAM_RESERVED;
+}
+
+impl PayoutStream {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        vault: Pubkey,
+        bump: u8,
+        recipient: Pubkey,
+        rent_payer: Pubkey,
+        rate: u64,
+        start: i64,
+        stop: i64,
+        now: i64,
+    ) -> Result<Self, ProgramError> {
+        // A stream starting in the past would pay its backlog out at once
+        if rate == 0 || start < now || start >= stop {
+            return Err(VaultError::InvalidStreamSchedule.into());
+        }
+        Ok(Self {
+            discriminator: STREAM_DISCRIMINATOR,
+            vault,
+            bump,
+            recipient,
+            rent_payer,
+            rate,
+            start,
+            stop,
+            settled_until: start,
+            total_paid: 0,
+            reserved: [0; STREAM_RESERVED],
+        })
+    }
+
+    // Lamports accrued since the last settlement
+    pub fn accrued(&self, now: i64) -> Result<u64, ProgramError> {
+        let until = std::cmp::min(now, self.stop);
+        if until <= self.settled_until {
+            return Ok(0);
+        }
+        (until - self.settled_until)
+            .unsigned_abs()
+            .checked_mul(self.rate)
+            .ok_or_else(|| VaultError::ArithmeticOverflow.into())
+    }
+
+    // Settle up to `max_amount` in whole seconds of accrual and return the
+    // amount to pay
+    pub fn settle(&mut self, now: i64, max_amount: u64) -> Result<u64, ProgramError> {
+        let amount = std::cmp::min(self.accrued(now)?, max_amount);
+        let seconds = amount / self.rate;
+        let paid = seconds * self.rate;
+
+        self.settled_until = self
+            .settled_until
+            .checked_add(seconds as i64)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        self.total_paid = self
+            .total_paid
+            .checked_add(paid)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        Ok(paid)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.settled_until >= self.stop
+    }
+}
+
+// Funded -> Released by the payer, Funded -> Refunded after expiry,
+// Funded -> Disputed by either party when there is an arbiter,
+// Disputed -> Released or Refunded by the arbiter, and Disputed -> Refunded
+// once the dispute has timed out
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub enum EscrowStatus {
+    Funded,
+    Disputed,
+    Released,
+    Refunded,
+}
+
+// Lamports held for a payee until the payer releases them, they expire back
+// to the payer, or an arbiter settles a dispute. The escrow account holds the
+// lamports itself.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct EscrowState {
+    pub discriminator: [u8; 8],
+    pub payer: Pubkey,
+    pub payee: Pubkey,
+    // The default pubkey when the escrow has no arbiter
+    pub arbiter: Pubkey,
+    pub seed: u64,
+    pub bump: u8,
+    pub amount: u64,
+    pub expires_at: i64,
+    pub status: EscrowStatus,
+    pub created_at: i64,
+    pub disputed_at: i64,
+    pub reserved: [u8; ESCROW_RESERVED],
+}
+
+impl ProgramAccount for EscrowState {
+    const DISCRIMINATOR: [u8; 8] = ESCROW_DISCRIMINATOR;
+    const LEN: usize = 8 + 32 + 32 + 32 + 8 + 1 + 8 + 8 + 1 + 8 + 8 + ESCROW_RESERVED;
+}
+
+impl EscrowState {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        payer: Pubkey,
+        payee: Pubkey,
+        arbiter: Pubkey,
+        seed: u64,
+        bump: u8,
+        amount: u64,
+        expires_at: i64,
+        now: i64,
+    ) -> Self {
+        Self {
+            discriminator: ESCROW_DISCRIMINATOR,
+            payer,
+            payee,
+            arbiter,
+            seed,
+            bump,
+            amount,
+            expires_at,
+            status: EscrowStatus::Funded,
+            created_at: now,
+            disputed_at: 0,
+            reserved: [0; ESCROW_RESERVED],
+        }
+    }
+
+    pub fn has_arbiter(&self) -> bool {
+        self.arbiter != Pubkey::default()
+    }
+
+    pub fn is_settled(&self) -> bool {
+        matches!(self.status, EscrowStatus::Released | EscrowStatus::Refunded)
+    }
+
+    pub fn dispute_deadline(&self) -> i64 {
+        self.disputed_at.saturating_add(ESCROW_DISPUTE_TIMEOUT)
+    }
+}
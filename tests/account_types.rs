@@ -0,0 +1,322 @@
+mod common;
+
+use common::*;
+use native::{
+    error::VaultError,
+    state::{
+        find_receipt_address, find_vault_address, DepositReceipt, EscrowState, MultisigConfig,
+        PayoutStream, PendingWithdrawal, ProgramAccount, RecipientAllowlist, VaultState,
+        VaultTokenState, VestingSchedule, WithdrawalProposal,
+    },
+};
+use solana_sdk::{
+    account_info::AccountInfo,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+type Loader = fn(&AccountInfo, &Pubkey) -> Result<(), ProgramError>;
+
+fn loader<T: ProgramAccount>(
+    account: &AccountInfo,
+    program_id: &Pubkey,
+) -> Result<(), ProgramError> {
+    T::load_account(account, program_id).map(|_| ())
+}
+
+struct Sample {
+    name: &'static str,
+    data: Vec<u8>,
+    len: usize,
+    load: Loader,
+    is_initialized: fn(&AccountInfo) -> Result<bool, ProgramError>,
+}
+
+fn sample<T: ProgramAccount>(name: &'static str, account: T) -> Sample {
+    Sample {
+        name,
+        data: serialize(&account),
+        len: T::LEN,
+        load: loader::<T>,
+        is_initialized: T::is_initialized,
+    }
+}
+
+// One initialized account of every type the program owns
+fn samples() -> Vec<Sample> {
+    let key = Pubkey::new_unique();
+    vec![
+        sample("vault", VaultState::new(key, key, 255, 1_000, 0)),
+        sample("vault token", VaultTokenState::new(key, key, 255)),
+        sample("receipt", DepositReceipt::new(key, key, 255)),
+        sample(
+            "pending withdrawal",
+            PendingWithdrawal::new(key, 0, 255, 1, key, key, 0, 0),
+        ),
+        sample(
+            "multisig",
+            MultisigConfig::new(key, 255, 1, &[key]).unwrap(),
+        ),
+        sample(
+            "proposal",
+            WithdrawalProposal::new(key, 0, 255, 1, key, key, 0),
+        ),
+        sample("allowlist", RecipientAllowlist::new(key, 255)),
+        sample(
+            "vesting",
+            VestingSchedule::new(key, 255, key, key, 1, 0, 0, 10, 0, false).unwrap(),
+        ),
+        sample(
+            "stream",
+            PayoutStream::new(key, 255, key, key, 1, 0, 10, 0).unwrap(),
+        ),
+        sample("escrow", EscrowState::new(key, key, key, 0, 255, 1, 10, 0)),
+    ]
+}
+
+// Data of the right length for `len` under a discriminator no program type uses
+fn foreign_data(len: usize) -> Vec<u8> {
+    let mut data = vec![0; len];
+    data[..8].copy_from_slice(b"FOREIGN\0");
+    data
+}
+
+fn with_account<R>(owner: &Pubkey, data: &[u8], f: impl FnOnce(&AccountInfo) -> R) -> R {
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = data.to_vec();
+    let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, owner, false, 0);
+    f(&account)
+}
+
+fn vault_error(error: VaultError) -> ProgramError {
+    error.into()
+}
+
+#[test]
+fn every_account_type_loads_at_its_declared_len() {
+    let program_id = Pubkey::new_unique();
+    for sample in samples() {
+        assert_eq!(sample.data.len(), sample.len, "{}", sample.name);
+        with_account(&program_id, &sample.data, |account| {
+            assert_eq!(
+                (sample.load)(account, &program_id),
+                Ok(()),
+                "{}",
+                sample.name
+            );
+            assert_eq!(
+                (sample.is_initialized)(account),
+                Ok(true),
+                "{}",
+                sample.name
+            );
+        });
+    }
+}
+
+#[test]
+fn loaders_reject_every_other_account_type() {
+    let program_id = Pubkey::new_unique();
+    let samples = samples();
+    for expected in &samples {
+        let foreign = (String::from("foreign"), foreign_data(expected.len));
+        let others = samples
+            .iter()
+            .filter(|stored| stored.name != expected.name)
+            .map(|stored| (String::from(stored.name), stored.data.clone()));
+        for (name, data) in others.chain([foreign]) {
+            with_account(&program_id, &data, |account| {
+                assert_eq!(
+                    (expected.load)(account, &program_id),
+                    Err(vault_error(VaultError::InvalidAccountType)),
+                    "{} loaded as {}",
+                    name,
+                    expected.name
+                );
+                assert_eq!(
+                    (expected.is_initialized)(account),
+                    Ok(false),
+                    "{} seen as {}",
+                    name,
+                    expected.name
+                );
+            });
+        }
+    }
+}
+
+#[test]
+fn loaders_reject_uninitialized_accounts() {
+    let program_id = Pubkey::new_unique();
+    for sample in samples() {
+        for data in [vec![], vec![0; sample.len]] {
+            with_account(&program_id, &data, |account| {
+                assert_eq!(
+                    (sample.load)(account, &program_id),
+                    Err(vault_error(VaultError::UninitializedVault)),
+                    "{}",
+                    sample.name
+                );
+                assert_eq!(
+                    (sample.is_initialized)(account),
+                    Ok(false),
+                    "{}",
+                    sample.name
+                );
+            });
+        }
+    }
+}
+
+#[test]
+fn loaders_reject_accounts_owned_by_another_program() {
+    let program_id = Pubkey::new_unique();
+    let other_program = Pubkey::new_unique();
+    for sample in samples() {
+        with_account(&other_program, &sample.data, |account| {
+            assert_eq!(
+                (sample.load)(account, &program_id),
+                Err(vault_error(VaultError::InvalidVaultOwner)),
+                "{}",
+                sample.name
+            );
+        });
+    }
+}
+
+// Every other account type, and a zeroed account, is refused as a vault by
+// deposit and withdraw
+#[tokio::test]
+async fn deposit_and_withdraw_reject_non_vault_accounts() {
+    let (mut program_test, program_id) = program_test();
+    let mut accounts = vec![];
+    let others = samples()
+        .into_iter()
+        .filter(|sample| sample.name != "vault")
+        .map(|sample| sample.data);
+    for data in others.chain([foreign_data(VaultState::LEN)]) {
+        let address = Pubkey::new_unique();
+        program_test.add_account(address, program_account(&program_id, data));
+        accounts.push((address, VaultError::InvalidAccountType));
+    }
+    let uninitialized = Pubkey::new_unique();
+    program_test.add_account(
+        uninitialized,
+        program_account(&program_id, vec![0; VaultState::LEN]),
+    );
+    accounts.push((uninitialized, VaultError::UninitializedVault));
+
+    let mut ctx = program_test.start_with_context().await;
+    let user = funded_keypair(&mut ctx, 10 * SOL).await;
+    for (address, expected) in accounts {
+        let result = process(
+            &mut ctx,
+            &[deposit(&program_id, &user.pubkey(), &address, SOL)],
+            &[&user],
+        )
+        .await;
+        assert_error(result, expected);
+
+        let result = process(
+            &mut ctx,
+            &[withdraw(
+                &program_id,
+                &address,
+                &user.pubkey(),
+                &user.pubkey(),
+            )],
+            &[&user],
+        )
+        .await;
+        assert_error(result, expected);
+    }
+}
+
+#[tokio::test]
+async fn initialize_vault_rejects_reinitialization() {
+    let (program_test, program_id) = program_test();
+    let mut ctx = program_test.start_with_context().await;
+    let owner = funded_keypair(&mut ctx, SOL).await;
+    let initialize = initialize_vault(&program_id, &owner.pubkey(), &owner.pubkey());
+
+    process(&mut ctx, std::slice::from_ref(&initialize), &[&owner])
+        .await
+        .unwrap();
+    let result = process(&mut ctx, &[initialize], &[&owner]).await;
+    assert_error(result, VaultError::AlreadyInitialized);
+}
+
+#[tokio::test]
+async fn initialize_vault_rejects_other_data_at_the_vault_address() {
+    let (mut program_test, program_id) = program_test();
+    let owner = Keypair::new();
+    let (vault, _) = find_vault_address(&owner.pubkey(), &program_id);
+    program_test.add_account(
+        vault,
+        program_account(&program_id, foreign_data(VaultState::LEN)),
+    );
+
+    let mut ctx = program_test.start_with_context().await;
+    fund(&mut ctx, &owner.pubkey(), SOL).await;
+
+    let result = process(
+        &mut ctx,
+        &[initialize_vault(
+            &program_id,
+            &owner.pubkey(),
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await;
+    assert_error(result, VaultError::InvalidAccountType);
+}
+
+// An account of another type sitting at a depositor's receipt address is
+// neither overwritten by deposit nor redeemed as a receipt
+#[tokio::test]
+async fn receipts_reject_other_account_types() {
+    let (mut program_test, program_id) = program_test();
+    let owner = Keypair::new();
+    let alice = Keypair::new();
+    let (vault, bump) = find_vault_address(&owner.pubkey(), &program_id);
+    let mut state = VaultState::new(owner.pubkey(), owner.pubkey(), bump, 1_000, 0);
+    state.total_deposited = SOL;
+    state.outstanding_principal = SOL;
+    let mut vault_account = program_account(&program_id, serialize(&state));
+    vault_account.lamports += SOL;
+    program_test.add_account(vault, vault_account);
+
+    let (receipt, _) = find_receipt_address(&vault, &alice.pubkey(), &program_id);
+    let token_state = VaultTokenState::new(vault, Pubkey::new_unique(), 255);
+    program_test.add_account(
+        receipt,
+        program_account(&program_id, serialize(&token_state)),
+    );
+
+    let mut ctx = program_test.start_with_context().await;
+    fund(&mut ctx, &alice.pubkey(), 10 * SOL).await;
+
+    let result = process(
+        &mut ctx,
+        &[deposit_with_receipt(
+            &program_id,
+            &alice.pubkey(),
+            &vault,
+            SOL,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_error(result, VaultError::InvalidAccountType);
+
+    let result = process(
+        &mut ctx,
+        &[redeem_deposit(&program_id, &vault, &alice.pubkey())],
+        &[&alice],
+    )
+    .await;
+    assert_error(result, VaultError::InvalidAccountType);
+}
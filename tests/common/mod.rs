@@ -0,0 +1,430 @@
+// Helpers shared by the program tests. Each test binary only uses some of them.
+#![allow(dead_code)]
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use native::{
+    error::VaultError,
+    processor::{process_instruction, TransferInstruction},
+    state::{find_escrow_address, find_receipt_address, find_stream_address, find_vault_address},
+};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    clock::Clock,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+    transaction::{Transaction, TransactionError},
+};
+
+pub const SOL: u64 = 1_000_000_000;
+
+pub fn program_test() -> (ProgramTest, Pubkey) {
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new("native", program_id, processor!(process_instruction));
+    (program_test, program_id)
+}
+
+// A program-owned account holding `data`, as the program would have created it
+pub fn program_account(program_id: &Pubkey, data: Vec<u8>) -> Account {
+    Account {
+        lamports: solana_sdk::rent::Rent::default().minimum_balance(data.len()),
+        data,
+        owner: *program_id,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+pub fn serialize<T: BorshSerialize>(account: &T) -> Vec<u8> {
+    account.try_to_vec().unwrap()
+}
+
+// Sign with the context payer, which pays every fee, plus `signers`. A fresh
+// blockhash keeps a retried instruction from being deduplicated.
+pub async fn process(
+    ctx: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers = vec![&ctx.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&ctx.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    ctx.banks_client.process_transaction(transaction).await
+}
+
+pub fn assert_error(result: Result<(), BanksClientError>, expected: VaultError) {
+    match result.expect_err("transaction should fail").unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            assert_eq!(code, expected as u32, "expected {expected:?}")
+        }
+        other => panic!("expected {expected:?}, got {other:?}"),
+    }
+}
+
+// Send `lamports` from the context payer
+pub async fn fund(ctx: &mut ProgramTestContext, address: &Pubkey, lamports: u64) {
+    let payer = ctx.payer.pubkey();
+    process(
+        ctx,
+        &[system_instruction::transfer(&payer, address, lamports)],
+        &[],
+    )
+    .await
+    .unwrap();
+}
+
+pub async fn funded_keypair(ctx: &mut ProgramTestContext, lamports: u64) -> Keypair {
+    let keypair = Keypair::new();
+    fund(ctx, &keypair.pubkey(), lamports).await;
+    keypair
+}
+
+pub async fn lamports(ctx: &mut ProgramTestContext, address: &Pubkey) -> u64 {
+    ctx.banks_client.get_balance(*address).await.unwrap()
+}
+
+pub async fn total_lamports(ctx: &mut ProgramTestContext, addresses: &[Pubkey]) -> u64 {
+    let mut total = 0;
+    for address in addresses {
+        total += lamports(ctx, address).await;
+    }
+    total
+}
+
+pub async fn rent_exempt(ctx: &mut ProgramTestContext, len: usize) -> u64 {
+    ctx.banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(len)
+}
+
+pub async fn load<T: BorshDeserialize>(ctx: &mut ProgramTestContext, address: &Pubkey) -> T {
+    let account = ctx
+        .banks_client
+        .get_account(*address)
+        .await
+        .unwrap()
+        .expect("account exists");
+    T::try_from_slice(&account.data).unwrap()
+}
+
+pub async fn now(ctx: &mut ProgramTestContext) -> i64 {
+    ctx.banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp
+}
+
+pub async fn warp_to(ctx: &mut ProgramTestContext, unix_timestamp: i64) {
+    let mut clock = ctx.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp = unix_timestamp;
+    ctx.set_sysvar(&clock);
+}
+
+fn instruction(
+    program_id: &Pubkey,
+    data: TransferInstruction,
+    accounts: Vec<AccountMeta>,
+) -> Instruction {
+    Instruction::new_with_bytes(*program_id, &data.try_to_vec().unwrap(), accounts)
+}
+
+pub fn vault_address(program_id: &Pubkey, owner: &Pubkey) -> Pubkey {
+    find_vault_address(owner, program_id).0
+}
+
+pub fn receipt_address(program_id: &Pubkey, vault: &Pubkey, depositor: &Pubkey) -> Pubkey {
+    find_receipt_address(vault, depositor, program_id).0
+}
+
+pub fn stream_address(program_id: &Pubkey, vault: &Pubkey, recipient: &Pubkey) -> Pubkey {
+    find_stream_address(vault, recipient, program_id).0
+}
+
+pub fn escrow_address(program_id: &Pubkey, payer: &Pubkey, payee: &Pubkey, seed: u64) -> Pubkey {
+    find_escrow_address(payer, payee, seed, program_id).0
+}
+
+pub fn initialize_vault(program_id: &Pubkey, owner: &Pubkey, authority: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::InitializeVault {
+            authority: *authority,
+            withdrawal_bps: 1_000,
+        },
+        vec![
+            AccountMeta::new(*owner, true),
+            AccountMeta::new(vault_address(program_id, owner), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+// A deposit without a receipt, which funds the authority
+pub fn deposit(
+    program_id: &Pubkey,
+    depositor: &Pubkey,
+    vault: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::DepositInstruction(amount),
+        vec![
+            AccountMeta::new(*depositor, true),
+            AccountMeta::new(*vault, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+// A deposit recorded on the depositor's receipt and owed back to them
+pub fn deposit_with_receipt(
+    program_id: &Pubkey,
+    depositor: &Pubkey,
+    vault: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut instruction = deposit(program_id, depositor, vault, amount);
+    instruction.accounts.push(AccountMeta::new(
+        receipt_address(program_id, vault, depositor),
+        false,
+    ));
+    instruction
+}
+
+pub fn redeem_deposit(program_id: &Pubkey, vault: &Pubkey, depositor: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::RedeemDeposit,
+        vec![
+            AccountMeta::new(*vault, false),
+            AccountMeta::new(receipt_address(program_id, vault, depositor), false),
+            AccountMeta::new(*depositor, true),
+        ],
+    )
+}
+
+pub fn withdraw(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    recipient: &Pubkey,
+    authority: &Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::WithdrawalInstruction,
+        vec![
+            AccountMeta::new(*vault, false),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+    )
+}
+
+pub fn withdraw_amount(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    recipient: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::WithdrawAmount(amount),
+        vec![
+            AccountMeta::new(*vault, false),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+    )
+}
+
+pub fn close_vault(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    destination: &Pubkey,
+    authority: &Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::CloseVault,
+        vec![
+            AccountMeta::new(*vault, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+    )
+}
+
+pub fn set_withdrawal_lock(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    authority: &Pubkey,
+    withdrawal_interval: i64,
+    unlock_at: i64,
+) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::SetWithdrawalLock {
+            withdrawal_interval,
+            unlock_at,
+        },
+        vec![
+            AccountMeta::new(*vault, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+    )
+}
+
+// The authority pays for the stream account and gets its rent back
+pub fn create_stream(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    authority: &Pubkey,
+    recipient: &Pubkey,
+    rate: u64,
+    start: i64,
+    stop: i64,
+) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::CreateStream {
+            recipient: *recipient,
+            rate,
+            start,
+            stop,
+        },
+        vec![
+            AccountMeta::new(*authority, true),
+            AccountMeta::new(*vault, false),
+            AccountMeta::new(stream_address(program_id, vault, recipient), false),
+            AccountMeta::new(*authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn cancel_stream(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    signer: &Pubkey,
+    recipient: &Pubkey,
+    rent_destination: &Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::CancelStream,
+        vec![
+            AccountMeta::new(*vault, false),
+            AccountMeta::new(stream_address(program_id, vault, recipient), false),
+            AccountMeta::new_readonly(*signer, true),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new(*rent_destination, false),
+        ],
+    )
+}
+
+pub fn create_escrow(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    payee: &Pubkey,
+    arbiter: &Pubkey,
+    seed: u64,
+    amount: u64,
+    expires_at: i64,
+) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::CreateEscrow {
+            payee: *payee,
+            arbiter: *arbiter,
+            seed,
+            amount,
+            expires_at,
+        },
+        vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(escrow_address(program_id, payer, payee, seed), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn release_escrow(
+    program_id: &Pubkey,
+    escrow: &Pubkey,
+    payer: &Pubkey,
+    payee: &Pubkey,
+) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::ReleaseEscrow,
+        vec![
+            AccountMeta::new(*escrow, false),
+            AccountMeta::new_readonly(*payer, true),
+            AccountMeta::new(*payee, false),
+        ],
+    )
+}
+
+pub fn refund_escrow(program_id: &Pubkey, escrow: &Pubkey, payer: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::RefundEscrow,
+        vec![
+            AccountMeta::new(*escrow, false),
+            AccountMeta::new(*payer, false),
+        ],
+    )
+}
+
+pub fn dispute_escrow(program_id: &Pubkey, escrow: &Pubkey, party: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::DisputeEscrow,
+        vec![
+            AccountMeta::new(*escrow, false),
+            AccountMeta::new_readonly(*party, true),
+        ],
+    )
+}
+
+pub fn resolve_escrow(
+    program_id: &Pubkey,
+    escrow: &Pubkey,
+    arbiter: &Pubkey,
+    destination: &Pubkey,
+    release: bool,
+) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::ResolveEscrow { release },
+        vec![
+            AccountMeta::new(*escrow, false),
+            AccountMeta::new_readonly(*arbiter, true),
+            AccountMeta::new(*destination, false),
+        ],
+    )
+}
+
+pub fn close_escrow(program_id: &Pubkey, escrow: &Pubkey, payer: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        TransferInstruction::CloseEscrow,
+        vec![
+            AccountMeta::new(*escrow, false),
+            AccountMeta::new(*payer, true),
+        ],
+    )
+}
@@ -0,0 +1,301 @@
+mod common;
+
+use common::*;
+use native::{
+    error::VaultError,
+    state::{EscrowState, EscrowStatus, ProgramAccount, ESCROW_DISPUTE_TIMEOUT},
+};
+use solana_program_test::{BanksClientError, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+const AMOUNT: u64 = 2 * SOL;
+const LIFETIME: i64 = 3_600;
+
+struct Escrow {
+    ctx: ProgramTestContext,
+    program_id: Pubkey,
+    payer: Keypair,
+    payee: Keypair,
+    arbiter: Keypair,
+    address: Pubkey,
+    expires_at: i64,
+}
+
+impl Escrow {
+    // A funded escrow, with an arbiter unless `with_arbiter` is false
+    async fn new(with_arbiter: bool) -> Self {
+        let (program_test, program_id) = program_test();
+        let mut ctx = program_test.start_with_context().await;
+        let payer = funded_keypair(&mut ctx, 10 * SOL).await;
+        let payee = funded_keypair(&mut ctx, SOL).await;
+        let arbiter = funded_keypair(&mut ctx, SOL).await;
+        let arbiter_key = if with_arbiter {
+            arbiter.pubkey()
+        } else {
+            Pubkey::default()
+        };
+        let expires_at = now(&mut ctx).await + LIFETIME;
+
+        process(
+            &mut ctx,
+            &[create_escrow(
+                &program_id,
+                &payer.pubkey(),
+                &payee.pubkey(),
+                &arbiter_key,
+                7,
+                AMOUNT,
+                expires_at,
+            )],
+            &[&payer],
+        )
+        .await
+        .unwrap();
+
+        let address = escrow_address(&program_id, &payer.pubkey(), &payee.pubkey(), 7);
+        Self {
+            ctx,
+            program_id,
+            payer,
+            payee,
+            arbiter,
+            address,
+            expires_at,
+        }
+    }
+
+    async fn state(&mut self) -> EscrowState {
+        load(&mut self.ctx, &self.address).await
+    }
+
+    async fn release(&mut self, signer: &Keypair) -> Result<(), BanksClientError> {
+        let instruction = release_escrow(
+            &self.program_id,
+            &self.address,
+            &signer.pubkey(),
+            &self.payee.pubkey(),
+        );
+        process(&mut self.ctx, &[instruction], &[signer]).await
+    }
+
+    async fn refund(&mut self) -> Result<(), BanksClientError> {
+        let instruction = refund_escrow(&self.program_id, &self.address, &self.payer.pubkey());
+        process(&mut self.ctx, &[instruction], &[]).await
+    }
+
+    async fn dispute(&mut self, party: &Keypair) -> Result<(), BanksClientError> {
+        let instruction = dispute_escrow(&self.program_id, &self.address, &party.pubkey());
+        process(&mut self.ctx, &[instruction], &[party]).await
+    }
+
+    async fn resolve(
+        &mut self,
+        arbiter: &Keypair,
+        destination: &Pubkey,
+        release: bool,
+    ) -> Result<(), BanksClientError> {
+        let instruction = resolve_escrow(
+            &self.program_id,
+            &self.address,
+            &arbiter.pubkey(),
+            destination,
+            release,
+        );
+        process(&mut self.ctx, &[instruction], &[arbiter]).await
+    }
+
+    // Lamports of the payer and the payee
+    async fn balances(&mut self) -> (u64, u64) {
+        let payer = self.payer.pubkey();
+        let payee = self.payee.pubkey();
+        (
+            lamports(&mut self.ctx, &payer).await,
+            lamports(&mut self.ctx, &payee).await,
+        )
+    }
+
+    // The escrow is settled and keeps only its rent reserve
+    async fn assert_settled(&mut self, status: EscrowStatus) {
+        assert_eq!(self.state().await.status, status);
+        let rent = rent_exempt(&mut self.ctx, EscrowState::LEN).await;
+        let address = self.address;
+        assert_eq!(lamports(&mut self.ctx, &address).await, rent);
+    }
+}
+
+#[tokio::test]
+async fn funded_to_released() {
+    let mut escrow = Escrow::new(true).await;
+    assert_eq!(escrow.state().await.status, EscrowStatus::Funded);
+    let (payer_before, payee_before) = escrow.balances().await;
+
+    // Only the payer can release
+    let payee = escrow.payee.insecure_clone();
+    assert_error(escrow.release(&payee).await, VaultError::InvalidAuthority);
+
+    let payer = escrow.payer.insecure_clone();
+    escrow.release(&payer).await.unwrap();
+    escrow.assert_settled(EscrowStatus::Released).await;
+    assert_eq!(
+        escrow.balances().await,
+        (payer_before, payee_before + AMOUNT)
+    );
+
+    // Settled escrows accept no further transitions
+    assert_error(escrow.release(&payer).await, VaultError::InvalidEscrowState);
+    warp_to(&mut escrow.ctx, escrow.expires_at).await;
+    assert_error(escrow.refund().await, VaultError::InvalidEscrowState);
+
+    // Closing returns the rent reserve to the payer
+    let rent = rent_exempt(&mut escrow.ctx, EscrowState::LEN).await;
+    let instruction = close_escrow(&escrow.program_id, &escrow.address, &payer.pubkey());
+    process(&mut escrow.ctx, &[instruction], &[&payer])
+        .await
+        .unwrap();
+    assert_eq!(lamports(&mut escrow.ctx, &escrow.address).await, 0);
+    assert_eq!(
+        escrow.balances().await,
+        (payer_before + rent, payee_before + AMOUNT)
+    );
+}
+
+#[tokio::test]
+async fn funded_to_refunded_only_after_expiry() {
+    let mut escrow = Escrow::new(true).await;
+    let (payer_before, payee_before) = escrow.balances().await;
+
+    assert_error(escrow.refund().await, VaultError::EscrowNotExpired);
+    warp_to(&mut escrow.ctx, escrow.expires_at - 1).await;
+    assert_error(escrow.refund().await, VaultError::EscrowNotExpired);
+
+    warp_to(&mut escrow.ctx, escrow.expires_at).await;
+    escrow.refund().await.unwrap();
+    escrow.assert_settled(EscrowStatus::Refunded).await;
+    assert_eq!(
+        escrow.balances().await,
+        (payer_before + AMOUNT, payee_before)
+    );
+
+    let payer = escrow.payer.insecure_clone();
+    assert_error(escrow.release(&payer).await, VaultError::InvalidEscrowState);
+}
+
+#[tokio::test]
+async fn dispute_requires_an_arbiter() {
+    let mut escrow = Escrow::new(false).await;
+    let payee = escrow.payee.insecure_clone();
+    assert_error(escrow.dispute(&payee).await, VaultError::NoArbiter);
+    assert_eq!(escrow.state().await.status, EscrowStatus::Funded);
+}
+
+#[tokio::test]
+async fn dispute_only_by_a_party_before_expiry() {
+    let mut escrow = Escrow::new(true).await;
+
+    let arbiter = escrow.arbiter.insecure_clone();
+    assert_error(escrow.dispute(&arbiter).await, VaultError::InvalidAuthority);
+
+    warp_to(&mut escrow.ctx, escrow.expires_at).await;
+    let payer = escrow.payer.insecure_clone();
+    assert_error(escrow.dispute(&payer).await, VaultError::EscrowExpired);
+    assert_eq!(escrow.state().await.status, EscrowStatus::Funded);
+}
+
+#[tokio::test]
+async fn funded_to_disputed_blocks_release_and_refund() {
+    let mut escrow = Escrow::new(true).await;
+    let payee = escrow.payee.insecure_clone();
+    escrow.dispute(&payee).await.unwrap();
+
+    let state = escrow.state().await;
+    assert_eq!(state.status, EscrowStatus::Disputed);
+    assert_eq!(state.disputed_at, now(&mut escrow.ctx).await);
+
+    let payer = escrow.payer.insecure_clone();
+    assert_error(escrow.release(&payer).await, VaultError::InvalidEscrowState);
+    assert_error(escrow.dispute(&payer).await, VaultError::InvalidEscrowState);
+    // Expiry alone does not end a dispute
+    warp_to(&mut escrow.ctx, escrow.expires_at).await;
+    assert_error(escrow.refund().await, VaultError::DisputeNotTimedOut);
+}
+
+#[tokio::test]
+async fn disputed_to_released_by_the_arbiter() {
+    let mut escrow = Escrow::new(true).await;
+    let (payer_before, payee_before) = escrow.balances().await;
+    let payer = escrow.payer.insecure_clone();
+    escrow.dispute(&payer).await.unwrap();
+
+    // Only the arbiter decides, and only towards the matching party
+    let payee = escrow.payee.pubkey();
+    assert_error(
+        escrow.resolve(&payer, &payee, true).await,
+        VaultError::InvalidAuthority,
+    );
+    let arbiter = escrow.arbiter.insecure_clone();
+    let payer_key = payer.pubkey();
+    assert_error(
+        escrow.resolve(&arbiter, &payer_key, true).await,
+        VaultError::InvalidRecipient,
+    );
+
+    escrow.resolve(&arbiter, &payee, true).await.unwrap();
+    escrow.assert_settled(EscrowStatus::Released).await;
+    assert_eq!(
+        escrow.balances().await,
+        (payer_before, payee_before + AMOUNT)
+    );
+    assert_error(
+        escrow.resolve(&arbiter, &payee, true).await,
+        VaultError::InvalidEscrowState,
+    );
+}
+
+#[tokio::test]
+async fn disputed_to_refunded_by_the_arbiter() {
+    let mut escrow = Escrow::new(true).await;
+    let (payer_before, payee_before) = escrow.balances().await;
+    let payee = escrow.payee.insecure_clone();
+    escrow.dispute(&payee).await.unwrap();
+
+    let arbiter = escrow.arbiter.insecure_clone();
+    let payer = escrow.payer.pubkey();
+    escrow.resolve(&arbiter, &payer, false).await.unwrap();
+    escrow.assert_settled(EscrowStatus::Refunded).await;
+    assert_eq!(
+        escrow.balances().await,
+        (payer_before + AMOUNT, payee_before)
+    );
+}
+
+// An arbiter who never acts cannot lock the escrow: once the dispute times out
+// anyone can refund the payer
+#[tokio::test]
+async fn disputed_to_refunded_after_timeout() {
+    let mut escrow = Escrow::new(true).await;
+    let (payer_before, payee_before) = escrow.balances().await;
+    let payee = escrow.payee.insecure_clone();
+    escrow.dispute(&payee).await.unwrap();
+    let deadline = escrow.state().await.disputed_at + ESCROW_DISPUTE_TIMEOUT;
+
+    warp_to(&mut escrow.ctx, deadline - 1).await;
+    assert_error(escrow.refund().await, VaultError::DisputeNotTimedOut);
+
+    warp_to(&mut escrow.ctx, deadline).await;
+    escrow.refund().await.unwrap();
+    escrow.assert_settled(EscrowStatus::Refunded).await;
+    assert_eq!(
+        escrow.balances().await,
+        (payer_before + AMOUNT, payee_before)
+    );
+
+    let arbiter = escrow.arbiter.insecure_clone();
+    let payee = escrow.payee.pubkey();
+    assert_error(
+        escrow.resolve(&arbiter, &payee, true).await,
+        VaultError::InvalidEscrowState,
+    );
+}
@@ -0,0 +1,409 @@
+mod common;
+
+use common::*;
+use native::{
+    error::VaultError,
+    state::{find_vault_address, DepositReceipt, ProgramAccount, VaultState},
+};
+use solana_sdk::signature::{Keypair, Signer};
+
+// Everything depositors put in comes back out through RedeemDeposit, the vault
+// holds exactly its rent reserve plus its recorded balance throughout, and
+// closing it returns the reserve
+#[tokio::test]
+async fn deposits_and_redemptions_conserve_lamports() {
+    let (program_test, program_id) = program_test();
+    let mut ctx = program_test.start_with_context().await;
+    let owner = funded_keypair(&mut ctx, SOL).await;
+    let alice = funded_keypair(&mut ctx, 10 * SOL).await;
+    let bob = funded_keypair(&mut ctx, 10 * SOL).await;
+    let vault = vault_address(&program_id, &owner.pubkey());
+
+    process(
+        &mut ctx,
+        &[initialize_vault(
+            &program_id,
+            &owner.pubkey(),
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    let vault_rent = rent_exempt(&mut ctx, VaultState::LEN).await;
+    let receipt_rent = rent_exempt(&mut ctx, DepositReceipt::LEN).await;
+    let accounts = [
+        owner.pubkey(),
+        alice.pubkey(),
+        bob.pubkey(),
+        vault,
+        receipt_address(&program_id, &vault, &alice.pubkey()),
+        receipt_address(&program_id, &vault, &bob.pubkey()),
+    ];
+    let total = total_lamports(&mut ctx, &accounts).await;
+
+    for (depositor, amount) in [(&alice, SOL), (&bob, 5 * SOL / 2), (&alice, SOL / 2)] {
+        process(
+            &mut ctx,
+            &[deposit_with_receipt(
+                &program_id,
+                &depositor.pubkey(),
+                &vault,
+                amount,
+            )],
+            &[depositor],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(total_lamports(&mut ctx, &accounts).await, total);
+        let state: VaultState = load(&mut ctx, &vault).await;
+        assert_eq!(
+            lamports(&mut ctx, &vault).await,
+            vault_rent + state.balance().unwrap()
+        );
+    }
+    assert_eq!(
+        lamports(&mut ctx, &alice.pubkey()).await,
+        10 * SOL - 3 * SOL / 2 - receipt_rent
+    );
+    assert_eq!(
+        lamports(&mut ctx, &bob.pubkey()).await,
+        10 * SOL - 5 * SOL / 2 - receipt_rent
+    );
+
+    for depositor in [&alice, &bob] {
+        process(
+            &mut ctx,
+            &[redeem_deposit(&program_id, &vault, &depositor.pubkey())],
+            &[depositor],
+        )
+        .await
+        .unwrap();
+        assert_eq!(total_lamports(&mut ctx, &accounts).await, total);
+    }
+    assert_eq!(
+        lamports(&mut ctx, &alice.pubkey()).await,
+        10 * SOL - receipt_rent
+    );
+    assert_eq!(
+        lamports(&mut ctx, &bob.pubkey()).await,
+        10 * SOL - receipt_rent
+    );
+    assert_eq!(lamports(&mut ctx, &vault).await, vault_rent);
+    let state: VaultState = load(&mut ctx, &vault).await;
+    assert_eq!(state.balance().unwrap(), 0);
+    assert_eq!(state.outstanding_principal, 0);
+
+    process(
+        &mut ctx,
+        &[close_vault(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert_eq!(lamports(&mut ctx, &vault).await, 0);
+    assert_eq!(lamports(&mut ctx, &owner.pubkey()).await, SOL);
+    assert_eq!(total_lamports(&mut ctx, &accounts).await, total);
+}
+
+// The authority withdraws what was deposited without a receipt, by policy
+// and by exact amount, while principal owed to depositors stays in the vault
+#[tokio::test]
+async fn authority_withdraws_its_funds_but_not_principal() {
+    let (program_test, program_id) = program_test();
+    let mut ctx = program_test.start_with_context().await;
+    let owner = funded_keypair(&mut ctx, 10 * SOL).await;
+    let alice = funded_keypair(&mut ctx, 10 * SOL).await;
+    let vault = vault_address(&program_id, &owner.pubkey());
+
+    process(
+        &mut ctx,
+        &[
+            initialize_vault(&program_id, &owner.pubkey(), &owner.pubkey()),
+            deposit(&program_id, &owner.pubkey(), &vault, 4 * SOL),
+            deposit_with_receipt(&program_id, &alice.pubkey(), &vault, 2 * SOL),
+        ],
+        &[&owner, &alice],
+    )
+    .await
+    .unwrap();
+
+    let vault_rent = rent_exempt(&mut ctx, VaultState::LEN).await;
+    let accounts = [owner.pubkey(), alice.pubkey(), vault];
+    let total = total_lamports(&mut ctx, &accounts).await;
+    let owner_before = lamports(&mut ctx, &owner.pubkey()).await;
+
+    // 10% of the authority's 4 SOL
+    process(
+        &mut ctx,
+        &[withdraw(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        lamports(&mut ctx, &owner.pubkey()).await,
+        owner_before + 4 * SOL / 10
+    );
+
+    // Everything else the authority deposited, and not a lamport more
+    let remaining = 4 * SOL - 4 * SOL / 10;
+    let result = process(
+        &mut ctx,
+        &[withdraw_amount(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+            remaining + 1,
+        )],
+        &[&owner],
+    )
+    .await;
+    assert_error(result, VaultError::InsufficientVaultFunds);
+    process(
+        &mut ctx,
+        &[withdraw_amount(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+            remaining,
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        lamports(&mut ctx, &owner.pubkey()).await,
+        owner_before + 4 * SOL
+    );
+    assert_eq!(lamports(&mut ctx, &vault).await, vault_rent + 2 * SOL);
+    assert_eq!(total_lamports(&mut ctx, &accounts).await, total);
+
+    // Only principal is left, and it is not the authority's
+    let result = process(
+        &mut ctx,
+        &[withdraw(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await;
+    assert_error(result, VaultError::WithdrawalTooSmall);
+    let result = process(
+        &mut ctx,
+        &[close_vault(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await;
+    assert_error(result, VaultError::OutstandingObligations);
+
+    let alice_before = lamports(&mut ctx, &alice.pubkey()).await;
+    process(
+        &mut ctx,
+        &[redeem_deposit(&program_id, &vault, &alice.pubkey())],
+        &[&alice],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        lamports(&mut ctx, &alice.pubkey()).await,
+        alice_before + 2 * SOL
+    );
+    assert_eq!(lamports(&mut ctx, &vault).await, vault_rent);
+}
+
+// The withdrawal lock holds the authority back but never depositors, even
+// when it is extended after they deposited
+#[tokio::test]
+async fn depositors_redeem_while_the_vault_is_locked() {
+    let (program_test, program_id) = program_test();
+    let mut ctx = program_test.start_with_context().await;
+    let owner = funded_keypair(&mut ctx, 10 * SOL).await;
+    let alice = funded_keypair(&mut ctx, 10 * SOL).await;
+    let vault = vault_address(&program_id, &owner.pubkey());
+
+    process(
+        &mut ctx,
+        &[
+            initialize_vault(&program_id, &owner.pubkey(), &owner.pubkey()),
+            deposit(&program_id, &owner.pubkey(), &vault, SOL),
+            deposit_with_receipt(&program_id, &alice.pubkey(), &vault, 2 * SOL),
+        ],
+        &[&owner, &alice],
+    )
+    .await
+    .unwrap();
+
+    let unlock_at = now(&mut ctx).await + 365 * 24 * 60 * 60;
+    process(
+        &mut ctx,
+        &[set_withdrawal_lock(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            0,
+            unlock_at,
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    let result = process(
+        &mut ctx,
+        &[withdraw(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await;
+    assert_error(result, VaultError::VaultLocked);
+
+    let alice_before = lamports(&mut ctx, &alice.pubkey()).await;
+    process(
+        &mut ctx,
+        &[redeem_deposit(&program_id, &vault, &alice.pubkey())],
+        &[&alice],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        lamports(&mut ctx, &alice.pubkey()).await,
+        alice_before + 2 * SOL
+    );
+}
+
+// A deposit that would overflow the vault's totals fails as a whole
+#[tokio::test]
+async fn deposit_overflow_is_rejected() {
+    let (mut program_test, program_id) = program_test();
+    let owner = Keypair::new();
+    let (vault, bump) = find_vault_address(&owner.pubkey(), &program_id);
+    let mut state = VaultState::new(owner.pubkey(), owner.pubkey(), bump, 1_000, 0);
+    state.total_deposited = u64::MAX;
+    state.total_withdrawn = u64::MAX;
+    program_test.add_account(vault, program_account(&program_id, serialize(&state)));
+
+    let mut ctx = program_test.start_with_context().await;
+    let alice = funded_keypair(&mut ctx, 10 * SOL).await;
+    let accounts = [alice.pubkey(), vault];
+    let total = total_lamports(&mut ctx, &accounts).await;
+
+    let result = process(
+        &mut ctx,
+        &[deposit(&program_id, &alice.pubkey(), &vault, SOL)],
+        &[&alice],
+    )
+    .await;
+    assert_error(result, VaultError::ArithmeticOverflow);
+
+    assert_eq!(lamports(&mut ctx, &alice.pubkey()).await, 10 * SOL);
+    assert_eq!(total_lamports(&mut ctx, &accounts).await, total);
+    let stored: VaultState = load(&mut ctx, &vault).await;
+    assert_eq!(stored, state);
+}
+
+// Withdrawals pay out at most what the vault holds above its rent-exempt
+// minimum, even when its recorded balance says more
+#[tokio::test]
+async fn withdrawals_stop_at_the_rent_exempt_floor() {
+    let (mut program_test, program_id) = program_test();
+    let owner = Keypair::new();
+    let (vault, bump) = find_vault_address(&owner.pubkey(), &program_id);
+    let mut state = VaultState::new(owner.pubkey(), owner.pubkey(), bump, 1_000, 0);
+    state.total_deposited = 10 * SOL;
+    let mut vault_account = program_account(&program_id, serialize(&state));
+    vault_account.lamports += SOL / 2;
+    program_test.add_account(vault, vault_account);
+
+    let mut ctx = program_test.start_with_context().await;
+    fund(&mut ctx, &owner.pubkey(), SOL).await;
+    let vault_rent = rent_exempt(&mut ctx, VaultState::LEN).await;
+    let accounts = [owner.pubkey(), vault];
+    let total = total_lamports(&mut ctx, &accounts).await;
+
+    let result = process(
+        &mut ctx,
+        &[withdraw_amount(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+            SOL / 2 + 1,
+        )],
+        &[&owner],
+    )
+    .await;
+    assert_error(result, VaultError::InsufficientVaultFunds);
+    process(
+        &mut ctx,
+        &[withdraw_amount(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+            SOL / 4,
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert_eq!(lamports(&mut ctx, &vault).await, vault_rent + SOL / 4);
+
+    // The policy asks for 10% of the recorded balance but only gets the rest
+    // of what sits above the floor
+    process(
+        &mut ctx,
+        &[withdraw(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert_eq!(lamports(&mut ctx, &vault).await, vault_rent);
+    assert_eq!(lamports(&mut ctx, &owner.pubkey()).await, SOL + SOL / 2);
+    assert_eq!(total_lamports(&mut ctx, &accounts).await, total);
+
+    let result = process(
+        &mut ctx,
+        &[withdraw(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await;
+    assert_error(result, VaultError::InsufficientVaultFunds);
+    assert_eq!(lamports(&mut ctx, &vault).await, vault_rent);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::*;
+use native::state::{PayoutStream, ProgramAccount, VaultState};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+const RATE: u64 = 1_000_000;
+
+// A vault funded by its owner with `funds` and a stream to a fresh recipient
+// that starts 10 seconds from now and runs for 1000 seconds
+async fn vault_with_stream(
+    funds: u64,
+) -> (ProgramTestContext, Pubkey, Keypair, Pubkey, Pubkey, i64) {
+    let (program_test, program_id) = program_test();
+    let mut ctx = program_test.start_with_context().await;
+    let owner = funded_keypair(&mut ctx, 10 * SOL).await;
+    let recipient = Keypair::new().pubkey();
+    let vault = vault_address(&program_id, &owner.pubkey());
+    let start = now(&mut ctx).await + 10;
+
+    process(
+        &mut ctx,
+        &[
+            initialize_vault(&program_id, &owner.pubkey(), &owner.pubkey()),
+            deposit(&program_id, &owner.pubkey(), &vault, funds),
+            create_stream(
+                &program_id,
+                &vault,
+                &owner.pubkey(),
+                &recipient,
+                RATE,
+                start,
+                start + 1_000,
+            ),
+        ],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let state: VaultState = load(&mut ctx, &vault).await;
+    assert_eq!(state.active_streams, 1);
+
+    (ctx, program_id, owner, vault, recipient, start)
+}
+
+// Cancelling closes the stream and frees the vault to close, whether the
+// accrual is paid in full or the vault can only cover part of it
+async fn cancel_then_close(funds: u64, elapsed: i64, expected_paid: u64) {
+    let (mut ctx, program_id, owner, vault, recipient, start) = vault_with_stream(funds).await;
+    let stream = stream_address(&program_id, &vault, &recipient);
+    let stream_rent = rent_exempt(&mut ctx, PayoutStream::LEN).await;
+    warp_to(&mut ctx, start + elapsed).await;
+
+    let owner_before = lamports(&mut ctx, &owner.pubkey()).await;
+    process(
+        &mut ctx,
+        &[cancel_stream(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &recipient,
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert_eq!(lamports(&mut ctx, &recipient).await, expected_paid);
+    assert_eq!(lamports(&mut ctx, &stream).await, 0);
+    assert_eq!(
+        lamports(&mut ctx, &owner.pubkey()).await,
+        owner_before + stream_rent
+    );
+    let state: VaultState = load(&mut ctx, &vault).await;
+    assert_eq!(state.active_streams, 0);
+
+    let vault_lamports = lamports(&mut ctx, &vault).await;
+    process(
+        &mut ctx,
+        &[close_vault(
+            &program_id,
+            &vault,
+            &owner.pubkey(),
+            &owner.pubkey(),
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert_eq!(lamports(&mut ctx, &vault).await, 0);
+    assert_eq!(
+        lamports(&mut ctx, &owner.pubkey()).await,
+        owner_before + stream_rent + vault_lamports
+    );
+}
+
+#[tokio::test]
+async fn cancelled_stream_pays_its_accrual_and_lets_the_vault_close() {
+    cancel_then_close(SOL, 100, 100 * RATE).await;
+}
+
+// The vault holds 50 seconds' worth of a 100 second accrual; the rest is
+// forfeited instead of keeping the stream open
+#[tokio::test]
+async fn cancelled_stream_forfeits_what_the_vault_cannot_cover() {
+    cancel_then_close(50 * RATE, 100, 50 * RATE).await;
+}